@@ -0,0 +1,52 @@
+//! Times `KDTree` construction and nearest-neighbour queries on a day 8 sized input: 1000
+//! random points in a 100000 wide cube, generated from a fixed seed so runs are comparable.
+//!
+//! Run with `cargo run --release --example kdtree_bench`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use advent_of_code::KDTree;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const POINTS: usize = 1000;
+const RUNS: usize = 1000;
+
+// the fastest run is the one least disturbed by whatever else the machine is doing
+fn time<R>(mut f: impl FnMut() -> R) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            black_box(f());
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let mut rng = StdRng::seed_from_u64(8);
+    let points: Vec<[u64; 3]> = (0..POINTS)
+        .map(|_| [(); 3].map(|_| rng.random_range(0..100_000)))
+        .collect();
+    let tree = KDTree::with_seed(&points, 8);
+
+    let construction = time(|| KDTree::with_seed(&points, 8));
+    let k_nearest = time(|| {
+        points
+            .iter()
+            .map(|&point| tree.k_nearest(point, 10).len())
+            .sum::<usize>()
+    });
+    let iter_nearest = time(|| {
+        points
+            .iter()
+            .map(|&point| tree.iter_nearest(point).take(10).count())
+            .sum::<usize>()
+    });
+
+    println!("construction:        {construction:?}");
+    println!("k_nearest x1000:     {k_nearest:?}");
+    println!("iter_nearest x1000:  {iter_nearest:?}");
+}
@@ -2,7 +2,7 @@ use chumsky::prelude::*;
 use chumsky::text::{digits, newline};
 advent_of_code::solution!(9);
 
-use advent_of_code::{Area, AreaKDTree};
+use advent_of_code::{Area, RectilinearPolygon};
 
 type Point = [u64; 2];
 
@@ -51,33 +51,21 @@ pub fn part_one(input: &str) -> Option<u64> {
 
 pub fn part_two(input: &str) -> Option<u64> {
     if let Some(points) = parse(input) {
-        if points.len() < 2 {
-            return None;
-        }
-        let mut segments: Vec<Area> = points
-            .windows(2)
-            .map(|w| Area::from_points(w[0], w[1]))
-            .collect();
-        segments.push(Area::from_points(
-            *points.last().unwrap(),
-            *points.first().unwrap(),
-        ));
-
-        let kdtree = AreaKDTree::new(&segments);
-        // kdtree.print();
+        let polygon = RectilinearPolygon::new(points.clone())?;
 
         let mut max_size = 0;
         for i in 0..points.len() {
             for j in (i + 1)..points.len() {
-                let area = Area::from_points(points[i], points[j]);
+                let area = Area::from_corners(points[i], points[j]);
 
-                let size = area.size();
-                if size > max_size && !kdtree.any_overlapping(&area) {
+                // too large for a u128 is too large for the answer as well
+                let size = area.lattice_points().unwrap_or(u128::MAX);
+                if size > max_size && polygon.contains_box(&area) {
                     max_size = size;
                 }
             }
         }
-        Some(max_size)
+        u64::try_from(max_size).ok()
     } else {
         None
     }
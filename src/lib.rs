@@ -1,849 +1,6623 @@
 pub mod template;
-use num_integer::gcd;
-use rand::{rng, rngs::ThreadRng, seq::IteratorRandom};
-use std::collections::{BinaryHeap, HashSet};
+use rand::{SeedableRng, rng, rngs::StdRng, seq::index};
+use smallvec::{SmallVec, smallvec};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
 use std::f32;
 use std::fmt::Display;
-use std::ops::{Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Sub, SubAssign};
-use std::rc::Rc;
+use std::hash::Hash;
+use std::ops::{
+    Add, AddAssign, Bound, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Range, RangeBounds,
+    RangeInclusive, Sub, SubAssign,
+};
+use tinyjson::JsonValue;
 
 pub type Point3d = [u64; 3];
 pub type Point2d = [u64; 2];
 
-pub fn points_dist<const DIMS: usize>(point1: &[u64; DIMS], point2: &[u64; DIMS]) -> u64 {
-    point1
-        .iter()
-        .zip(point2.iter())
-        .map(|(d1, d2)| d1.abs_diff(*d2).pow(2))
-        .sum()
-}
+/// A coordinate type that can be stored in a `KDTree`.
+///
+/// `Dist` is the type squared distances are accumulated in, which is wider than the coordinate
+/// itself for integers so that squaring an axis distance does not overflow. Sums of several
+/// axes saturate instead, so two full-range `u64` axes give `u128::MAX`.
+pub trait Coordinate: Copy + PartialOrd + std::fmt::Debug {
+    type Dist: Copy
+        + PartialOrd
+        + Add<Output = Self::Dist>
+        + Mul<Output = Self::Dist>
+        + std::iter::Sum
+        + std::fmt::Debug;
 
-#[derive(Debug)]
-pub enum KDTree<const DIMS: usize> {
-    Node {
-        left: Rc<KDTree<DIMS>>,
-        right: Rc<KDTree<DIMS>>,
-        pivot: u64,
-        axis: u32,
-    },
-    Leaf {
-        points: Vec<[u64; DIMS]>,
-    },
+    /// Absolute difference between two coordinates, in the distance type.
+    fn axis_dist(self, other: Self) -> Self::Dist;
+
+    /// Sum of two distances, clamped to the largest representable distance.
+    fn saturating_add_dist(a: Self::Dist, b: Self::Dist) -> Self::Dist;
+
+    /// Product of two distances, `None` if it isn't representable.
+    fn checked_mul_dist(a: Self::Dist, b: Self::Dist) -> Option<Self::Dist>;
+
+    /// Value halfway between two coordinates, rounded in whichever direction is convenient.
+    fn midpoint(self, other: Self) -> Self;
+
+    /// Value used when exporting trees to JSON: a number if it fits an `f64` exactly, otherwise
+    /// a decimal string, so large integers don't silently lose precision.
+    fn to_json(self) -> JsonValue;
 }
 
-impl<const DIMS: usize> KDTree<DIMS> {
-    pub fn new(points: &[[u64; DIMS]]) -> Rc<KDTree<DIMS>> {
-        let mut rng = rng();
-        KDTree::construct_recur(points, &mut rng, 0)
-    }
-    fn construct_recur(
-        points: &[[u64; DIMS]],
-        rng: &mut ThreadRng,
-        mut axis: u32,
-    ) -> Rc<KDTree<DIMS>> {
-        // it's faster to iterate over a small number of elements than to create a deep tree
-        if points.len() <= 8 {
-            Rc::new(KDTree::Leaf {
-                points: points.into(),
-            })
-        } else {
-            // try splitting on either axis,
-            for _ in 0..DIMS {
-                let axis_idx = axis as usize;
-                let pivot = {
-                    let mut sample: Vec<[u64; DIMS]> = points
-                        .iter()
-                        .choose_multiple(rng, std::cmp::min(7, points.len() / 2))
-                        .into_iter()
-                        .map(|&point| point)
-                        .collect();
-                    sample.sort_by_key(|point| point[axis_idx]);
-                    sample[sample.len() / 2][axis_idx]
-                };
-                let left: Vec<[u64; DIMS]> = points
-                    .iter()
-                    .cloned()
-                    .filter(|point| point[axis_idx] < pivot)
-                    .collect();
-                let right: Vec<[u64; DIMS]> = points
-                    .iter()
-                    .cloned()
-                    .filter(|point| point[axis_idx] >= pivot)
-                    .collect();
-                let new_axis = (axis + 1) % (DIMS as u32);
-                if left.len() != points.len() && right.len() != points.len() {
-                    return Rc::new(KDTree::Node {
-                        left: KDTree::construct_recur(&left, rng, new_axis),
-                        right: KDTree::construct_recur(&right, rng, new_axis),
-                        pivot,
-                        axis,
-                    });
+macro_rules! impl_coordinate_int {
+    ($($t:ty),*) => {
+        $(
+            impl Coordinate for $t {
+                type Dist = u128;
+
+                fn axis_dist(self, other: Self) -> u128 {
+                    self.abs_diff(other) as u128
+                }
+
+                fn saturating_add_dist(a: u128, b: u128) -> u128 {
+                    a.saturating_add(b)
+                }
+
+                fn checked_mul_dist(a: u128, b: u128) -> Option<u128> {
+                    a.checked_mul(b)
+                }
+
+                fn midpoint(self, other: Self) -> Self {
+                    <$t>::midpoint(self, other)
+                }
+
+                fn to_json(self) -> JsonValue {
+                    // every integer up to 2^53 has an exact f64
+                    if (self as i128).unsigned_abs() <= 1 << 53 {
+                        JsonValue::Number(self as f64)
+                    } else {
+                        JsonValue::String(self.to_string())
+                    }
                 }
-                axis = new_axis;
             }
-            Rc::new(KDTree::Leaf {
-                points: points.into(),
-            })
-        }
+        )*
+    };
+}
+
+impl_coordinate_int!(i32, i64, u64);
+
+impl Coordinate for f64 {
+    type Dist = f64;
+
+    fn axis_dist(self, other: Self) -> f64 {
+        (self - other).abs()
     }
 
-    pub fn print(&self) {
-        KDTree::<DIMS>::print_recur(self, 0);
+    fn saturating_add_dist(a: f64, b: f64) -> f64 {
+        a + b
     }
 
-    fn print_recur(kdtree: &KDTree<DIMS>, indent: usize) {
-        match kdtree {
-            Self::Node {
-                left,
-                right,
-                pivot,
-                axis,
-            } => {
-                println!(
-                    "{} Node, pivot: {}, axis: {}",
-                    " ".repeat(indent),
-                    pivot,
-                    axis
-                );
-                KDTree::print_recur(left, indent + 2);
-                KDTree::print_recur(right, indent + 2);
-            }
-            Self::Leaf { points } => {
-                println!("{} Leaf {:?}", " ".repeat(indent), points);
-            }
-        }
+    fn checked_mul_dist(a: f64, b: f64) -> Option<f64> {
+        Some(a * b).filter(|v| v.is_finite())
+    }
+
+    fn midpoint(self, other: Self) -> Self {
+        f64::midpoint(self, other)
     }
 
-    pub fn iter_nearest<'a>(&'a self, to: [u64; DIMS]) -> KDTreeNearestIter<'a, DIMS> {
-        KDTreeNearestIter::new(self, to)
+    fn to_json(self) -> JsonValue {
+        JsonValue::Number(self)
     }
 }
 
-#[derive(Debug)]
-struct KDTreeNearestIterHeapItem<'a, const DIMS: usize> {
-    item: &'a [u64; DIMS],
-    dist: u64,
+pub fn points_dist<T: Coordinate, const DIMS: usize>(
+    point1: &[T; DIMS],
+    point2: &[T; DIMS],
+) -> T::Dist {
+    point1
+        .iter()
+        .zip(point2.iter())
+        .map(|(&d1, &d2)| {
+            let d = d1.axis_dist(d2);
+            d * d
+        })
+        .fold(std::iter::empty().sum(), T::saturating_add_dist)
 }
 
-impl<'a, const DIMS: usize> Ord for KDTreeNearestIterHeapItem<'a, DIMS> {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        other.dist.cmp(&self.dist)
-    }
+fn cmp_coords<T: PartialOrd>(a: &T, b: &T) -> std::cmp::Ordering {
+    a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal)
 }
 
-impl<'a, const DIMS: usize> PartialOrd for KDTreeNearestIterHeapItem<'a, DIMS> {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
+/// A distance function for nearest-neighbour queries.
+pub trait Metric<T: Coordinate> {
+    fn dist<const DIMS: usize>(&self, point1: &[T; DIMS], point2: &[T; DIMS]) -> T::Dist;
+
+    /// Lower bound on the distance to any point on the other side of a splitting plane that is
+    /// `axis_dist` away along its axis. Used to prune subtrees that can't contain closer points.
+    fn axis_bound(&self, axis_dist: T::Dist) -> T::Dist;
+}
+
+/// Squared Euclidean distance, as computed by `points_dist`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SquaredEuclidean;
+
+impl<T: Coordinate> Metric<T> for SquaredEuclidean {
+    fn dist<const DIMS: usize>(&self, point1: &[T; DIMS], point2: &[T; DIMS]) -> T::Dist {
+        points_dist(point1, point2)
+    }
+
+    fn axis_bound(&self, axis_dist: T::Dist) -> T::Dist {
+        axis_dist * axis_dist
     }
 }
 
-impl<'a, const DIMS: usize> PartialEq for KDTreeNearestIterHeapItem<'a, DIMS> {
-    fn eq(&self, other: &Self) -> bool {
-        self.dist == other.dist
+/// Manhattan (L1) distance, the sum of the distances along each axis.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Manhattan;
+
+impl<T: Coordinate> Metric<T> for Manhattan {
+    fn dist<const DIMS: usize>(&self, point1: &[T; DIMS], point2: &[T; DIMS]) -> T::Dist {
+        point1
+            .iter()
+            .zip(point2.iter())
+            .map(|(&d1, &d2)| d1.axis_dist(d2))
+            .fold(std::iter::empty().sum(), T::saturating_add_dist)
+    }
+
+    fn axis_bound(&self, axis_dist: T::Dist) -> T::Dist {
+        axis_dist
     }
 }
 
-impl<'a, const DIMS: usize> Eq for KDTreeNearestIterHeapItem<'a, DIMS> {}
+/// Chebyshev (L∞) distance, the largest distance along any one axis.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Chebyshev;
 
-#[derive(Debug)]
-pub struct KDTreeNearestIter<'a, const DIMS: usize> {
-    target: [u64; DIMS],
-    nearest_items: BinaryHeap<KDTreeNearestIterHeapItem<'a, DIMS>>,
-    pruned_branches: Vec<(&'a KDTree<DIMS>, bool)>,
-}
-
-impl<'a, const DIMS: usize> KDTreeNearestIter<'a, DIMS> {
-    fn walk_to_nearest(
-        mut tree: &'a KDTree<DIMS>,
-        point: &[u64; DIMS],
-        nearest_items: &mut BinaryHeap<KDTreeNearestIterHeapItem<'a, DIMS>>,
-        pruned_branches: &mut Vec<(&'a KDTree<DIMS>, bool)>,
-    ) {
-        loop {
-            match tree {
-                KDTree::Node {
-                    left,
-                    right,
-                    pivot,
-                    axis,
-                } => {
-                    if point[*axis as usize] < *pivot {
-                        pruned_branches.push((tree, true));
-                        tree = left;
-                    } else {
-                        pruned_branches.push((tree, false));
-                        tree = right;
-                    }
-                }
-                KDTree::Leaf { points } => {
-                    for near_point in points {
-                        nearest_items.push(KDTreeNearestIterHeapItem {
-                            item: near_point,
-                            dist: points_dist(&point, near_point),
-                        });
-                    }
-                    return;
-                }
-            }
-        }
+impl<T: Coordinate> Metric<T> for Chebyshev {
+    fn dist<const DIMS: usize>(&self, point1: &[T; DIMS], point2: &[T; DIMS]) -> T::Dist {
+        point1
+            .iter()
+            .zip(point2.iter())
+            .map(|(&d1, &d2)| d1.axis_dist(d2))
+            .max_by(cmp_coords)
+            // zero-dimensional points, the empty sum is zero
+            .unwrap_or_else(|| std::iter::empty().sum())
     }
 
-    pub fn new(tree: &'a KDTree<DIMS>, point: [u64; DIMS]) -> KDTreeNearestIter<'a, DIMS> {
-        let mut nearest_items = BinaryHeap::new();
-        let mut pruned_branches = Vec::new();
-        Self::walk_to_nearest(tree, &point, &mut nearest_items, &mut pruned_branches);
-        let result = KDTreeNearestIter {
-            target: point,
-            nearest_items,
-            pruned_branches,
-        };
-        result
+    fn axis_bound(&self, axis_dist: T::Dist) -> T::Dist {
+        axis_dist
     }
 }
 
-impl<'a, const DIMS: usize> Iterator for KDTreeNearestIter<'a, DIMS> {
-    type Item = &'a [u64; DIMS];
+/// How spatial trees choose the value to split on at each node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PivotStrategy {
+    /// Median of a small random sample, seeded differently on every run.
+    #[default]
+    RandomSample,
+    /// Median of a small random sample, drawn from a generator with a fixed seed, so the same
+    /// input always results in the same tree.
+    SeededSample(u64),
+    /// Exact median of all values along the split axis.
+    Median,
+    /// Halfway between the smallest and largest value along the split axis.
+    Midpoint,
+}
+
+struct PivotPicker {
+    strategy: PivotStrategy,
+    rng: StdRng,
+}
 
-    fn next(&mut self) -> Option<&'a [u64; DIMS]> {
-        let KDTreeNearestIter {
-            target,
-            nearest_items,
-            pruned_branches,
-        } = self;
-        let mut idx = 0;
+impl PivotPicker {
+    fn new(strategy: PivotStrategy) -> PivotPicker {
+        let rng = match strategy {
+            PivotStrategy::RandomSample => StdRng::from_rng(&mut rng()),
+            PivotStrategy::SeededSample(seed) => StdRng::seed_from_u64(seed),
+            // not used, so no need to pay for seeding from the OS
+            PivotStrategy::Median | PivotStrategy::Midpoint => StdRng::seed_from_u64(0),
+        };
+        PivotPicker { strategy, rng }
+    }
 
-        while idx < pruned_branches.len() {
-            let mut to_add = None;
-            if let (
-                KDTree::Node {
-                    pivot,
-                    axis,
-                    left,
-                    right,
-                },
-                right_tree,
-            ) = pruned_branches[idx]
-            {
-                if nearest_items.is_empty()
-                    || pivot.abs_diff(target[(*axis) as usize]).pow(2)
-                        <= nearest_items.peek().unwrap().dist
-                {
-                    to_add = if right_tree { Some(right) } else { Some(left) };
-                }
+    // pick a pivot from the `len` (at least one) values along the split axis
+    fn pick<T: Coordinate>(&mut self, len: usize, value_at: impl Fn(usize) -> T) -> T {
+        match self.strategy {
+            PivotStrategy::RandomSample | PivotStrategy::SeededSample(_) => {
+                let sample_size = std::cmp::min(7, len / 2).max(1);
+                let mut sample: SmallVec<[T; 8]> = index::sample(&mut self.rng, len, sample_size)
+                    .into_iter()
+                    .map(value_at)
+                    .collect();
+                sample.sort_by(cmp_coords);
+                sample[sample.len() / 2]
             }
-            if let Some(subtree) = to_add {
-                pruned_branches.remove(idx);
-                Self::walk_to_nearest(subtree, &target, nearest_items, pruned_branches);
-            } else {
-                idx += 1;
+            PivotStrategy::Median => {
+                let mut values: Vec<T> = (0..len).map(value_at).collect();
+                *values.select_nth_unstable_by(len / 2, cmp_coords).1
+            }
+            PivotStrategy::Midpoint => {
+                let (min, max) = (1..len).map(&value_at).fold(
+                    (value_at(0), value_at(0)),
+                    |(min, max), value| {
+                        (
+                            if value < min { value } else { min },
+                            if value > max { value } else { max },
+                        )
+                    },
+                );
+                // values equal to the pivot go right, so make sure the pivot is above the minimum
+                let mid = min.midpoint(max);
+                if mid > min { mid } else { max }
             }
         }
-        nearest_items.pop().map(|i| i.item)
     }
 }
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
-pub struct Area {
-    bottom_left: Point2d,
-    top_right: Point2d,
+// move the values matching `pred` to the front, returning how many there are
+fn partition_in_place<V>(values: &mut [V], pred: impl Fn(&V) -> bool) -> usize {
+    let mut split = 0;
+    for i in 0..values.len() {
+        if pred(&values[i]) {
+            values.swap(split, i);
+            split += 1;
+        }
+    }
+    split
+}
+
+/// Shape statistics of a `KDTree` or `AreaKDTree`, to judge how well a pivot strategy works.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TreeStats {
+    /// Number of inner nodes.
+    pub nodes: usize,
+    /// Number of leaves.
+    pub leaves: usize,
+    /// Number of edges on the longest path from the root to a leaf.
+    pub depth: usize,
+    /// Number of leaves of each size.
+    pub leaf_sizes: BTreeMap<usize, usize>,
+    /// Number of extra copies of items stored in more than one leaf. Always zero for `KDTree`,
+    /// for `AreaKDTree` it counts areas stored on both sides of a pivot.
+    pub duplicated: usize,
+    /// Largest fraction of a node's items that ended up in one of its children. 0.5 is a
+    /// perfectly balanced tree, 1.0 means a split that separated nothing.
+    pub balance_factor: f64,
 }
 
-impl Area {
-    pub fn from_points(p1: Point2d, p2: Point2d) -> Self {
-        Area {
-            bottom_left: [p1[1].min(p2[1]), p1[0].min(p2[0])],
-            top_right: [p1[1].max(p2[1]), p1[0].max(p2[0])],
+impl TreeStats {
+    fn add_node(&mut self, left_len: usize, right_len: usize) {
+        self.nodes += 1;
+        let len = left_len + right_len;
+        if len > 0 {
+            let balance = left_len.max(right_len) as f64 / len as f64;
+            self.balance_factor = self.balance_factor.max(balance);
         }
     }
 
-    pub fn bottom(&self) -> u64 {
-        self.bottom_left[0]
-    }
-    pub fn left(&self) -> u64 {
-        self.bottom_left[1]
-    }
-    pub fn top(&self) -> u64 {
-        self.top_right[0]
-    }
-    pub fn right(&self) -> u64 {
-        self.top_right[1]
+    fn add_leaf(&mut self, depth: usize, len: usize) {
+        self.leaves += 1;
+        self.depth = self.depth.max(depth);
+        *self.leaf_sizes.entry(len).or_default() += 1;
     }
+}
 
-    pub fn size(&self) -> u64 {
-        (self.top_right[0] - self.bottom_left[0] + 1)
-            * (self.top_right[1] - self.bottom_left[1] + 1)
-    }
+fn json_object<const N: usize>(entries: [(&str, JsonValue); N]) -> JsonValue {
+    JsonValue::Object(
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+    )
+}
 
-    pub fn overlaps(&self, other: &Self) -> bool {
-        self.left() < other.right()
-            && self.right() > other.left()
-            && self.top() > other.bottom()
-            && self.bottom() < other.top()
-    }
+fn json_coords<T: Coordinate>(coords: &[T]) -> JsonValue {
+    JsonValue::Array(coords.iter().map(|&c| c.to_json()).collect())
 }
 
-#[derive(Debug, Hash, PartialEq, Eq)]
-pub enum AreaKDTree {
+// leaves are split once they grow past this through insertion
+const KDTREE_MAX_LEAF_SIZE: usize = 16;
+// a subtree is rebuilt when one of its children holds more than this fraction of its points
+const KDTREE_BALANCE_FACTOR: f64 = 0.75;
+
+#[derive(Debug, Clone)]
+enum KDTreeNode<T> {
     Node {
-        left: Rc<AreaKDTree>,
-        right: Rc<AreaKDTree>,
-        pivot: u64,
+        left: usize,
+        right: usize,
+        pivot: T,
         axis: u32,
+        len: usize,
+        // number of points when an exact median split still came out lopsided because too many
+        // of them tie with the pivot, zero otherwise
+        stuck_len: usize,
     },
     Leaf {
-        areas: Vec<Area>,
+        // the leaf owns `capacity` slots of `leaf_points` from `start`, the first `len` are used
+        start: usize,
+        len: usize,
+        capacity: usize,
     },
 }
 
-impl AreaKDTree {
-    pub fn new(areas: &[Area]) -> Rc<AreaKDTree> {
-        let mut rng = rng();
-        AreaKDTree::construct_recur(areas, &mut rng, 0)
+/// A k-d tree over points. Nodes live in a single arena and refer to their children by index,
+/// so the tree can be shared between threads. The points of all leaves share one array too.
+///
+/// Every point is identified by an index: its position in the slice the tree was built from,
+/// or the value returned by `insert`. Queries report it alongside the point, so points with
+/// identical coordinates can still be told apart.
+#[derive(Debug, Clone)]
+pub struct KDTree<T, const DIMS: usize> {
+    nodes: Vec<KDTreeNode<T>>,
+    // nodes discarded by rebuilds, reused before growing the arena
+    free_nodes: Vec<usize>,
+    root: usize,
+    // each point is stored together with its index
+    leaf_points: Vec<([T; DIMS], usize)>,
+    // slots no leaf owns any more, reclaimed by compacting once they are the majority
+    unused_slots: usize,
+    // coordinates of every point by index, `None` once removed
+    points_by_index: Vec<Option<[T; DIMS]>>,
+}
+
+/// A point found by a `KDTree` query: its index, its coordinates and its distance to the target.
+pub type Neighbour<'a, T, const DIMS: usize> = (usize, &'a [T; DIMS], <T as Coordinate>::Dist);
+
+impl<T: Coordinate, const DIMS: usize> KDTree<T, DIMS> {
+    pub fn new(points: &[[T; DIMS]]) -> KDTree<T, DIMS> {
+        KDTree::with_pivot_strategy(points, PivotStrategy::RandomSample)
+    }
+
+    /// Build a tree whose shape only depends on the points and the seed.
+    pub fn with_seed(points: &[[T; DIMS]], seed: u64) -> KDTree<T, DIMS> {
+        KDTree::with_pivot_strategy(points, PivotStrategy::SeededSample(seed))
     }
-    fn construct_recur(areas: &[Area], rng: &mut ThreadRng, mut axis: u32) -> Rc<AreaKDTree> {
+
+    pub fn with_pivot_strategy(points: &[[T; DIMS]], strategy: PivotStrategy) -> KDTree<T, DIMS> {
+        let mut points: Vec<([T; DIMS], usize)> = points
+            .iter()
+            .enumerate()
+            .map(|(i, &point)| (point, i))
+            .collect();
+        let mut tree = KDTree {
+            nodes: Vec::with_capacity(points.len() / 4 + 1),
+            free_nodes: Vec::new(),
+            root: 0,
+            leaf_points: Vec::new(),
+            unused_slots: 0,
+            points_by_index: points.iter().map(|&(point, _)| Some(point)).collect(),
+        };
+        tree.root = tree.construct_recur(&mut points, &mut PivotPicker::new(strategy), 0, 0);
+        // partitioning leaves the points of every leaf next to each other
+        tree.leaf_points = points;
+        tree
+    }
+
+    fn alloc(&mut self, node: KDTreeNode<T>) -> usize {
+        if let Some(idx) = self.free_nodes.pop() {
+            self.nodes[idx] = node;
+            idx
+        } else {
+            self.nodes.push(node);
+            self.nodes.len() - 1
+        }
+    }
+
+    // `start` is where `points` will be in `leaf_points` once the caller stores them there
+    fn construct_recur(
+        &mut self,
+        points: &mut [([T; DIMS], usize)],
+        picker: &mut PivotPicker,
+        axis: u32,
+        start: usize,
+    ) -> usize {
+        let node = self.construct_node(points, picker, axis, start);
+        self.alloc(node)
+    }
+
+    fn construct_node(
+        &mut self,
+        points: &mut [([T; DIMS], usize)],
+        picker: &mut PivotPicker,
+        mut axis: u32,
+        start: usize,
+    ) -> KDTreeNode<T> {
+        let leaf = KDTreeNode::Leaf {
+            start,
+            len: points.len(),
+            capacity: points.len(),
+        };
         // it's faster to iterate over a small number of elements than to create a deep tree
-        if areas.len() <= 8 {
-            Rc::new(AreaKDTree::Leaf {
-                areas: areas.into(),
-            })
+        if points.len() <= 8 {
+            leaf
         } else {
             // try splitting on either axis,
-            for _ in 0..2 {
+            for _ in 0..DIMS {
                 let axis_idx = axis as usize;
-                let pivot = {
-                    let mut sample: Vec<Point2d> = areas
-                        .iter()
-                        .choose_multiple(rng, std::cmp::min(7, areas.len() / 2))
-                        .iter()
-                        .flat_map(|area| [area.bottom_left, area.top_right])
-                        .collect();
-                    sample.sort_by_key(|point| point[axis_idx]);
-                    sample[sample.len() / 2][axis_idx]
-                };
-                let left: Vec<Area> = areas
-                    .iter()
-                    .cloned()
-                    .filter(|area| {
-                        let v1 = area.bottom_left[axis_idx];
-                        v1 < pivot
-                    })
-                    .collect();
-                let right: Vec<Area> = areas
-                    .iter()
-                    .cloned()
-                    .filter(|area| {
-                        let v1 = area.top_right[axis_idx];
-                        v1 >= pivot
-                    })
-                    .collect();
-                let new_axis = (axis + 1) % 2;
-                if left.len() != areas.len() && right.len() != areas.len() {
-                    return Rc::new(AreaKDTree::Node {
-                        left: AreaKDTree::construct_recur(&left, rng, new_axis),
-                        right: AreaKDTree::construct_recur(&right, rng, new_axis),
+                let pivot = picker.pick(points.len(), |i| points[i].0[axis_idx]);
+                let split = partition_in_place(points, |(point, _)| point[axis_idx] < pivot);
+                let new_axis = (axis + 1) % (DIMS as u32);
+                if split != 0 && split != points.len() {
+                    let len = points.len();
+                    let stuck_len = if picker.strategy == PivotStrategy::Median
+                        && !Self::is_balanced(split, len - split)
+                    {
+                        len
+                    } else {
+                        0
+                    };
+                    let (left, right) = points.split_at_mut(split);
+                    return KDTreeNode::Node {
+                        left: self.construct_recur(left, picker, new_axis, start),
+                        right: self.construct_recur(right, picker, new_axis, start + split),
                         pivot,
                         axis,
-                    });
+                        len,
+                        stuck_len,
+                    };
                 }
                 axis = new_axis;
             }
-            Rc::new(AreaKDTree::Leaf {
-                areas: areas.into(),
-            })
+            leaf
         }
     }
 
-    pub fn any_overlapping(&self, area: &Area) -> bool {
-        match self {
-            Self::Node {
-                left,
-                right,
-                pivot,
-                axis,
-            } => {
-                if area.bottom_left[*axis as usize] < *pivot && Self::any_overlapping(left, area) {
-                    return true;
-                }
-                if area.top_right[*axis as usize] >= *pivot {
-                    return Self::any_overlapping(right, area);
-                }
-                false
+    pub fn len(&self) -> usize {
+        self.node_len(self.root)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn node_len(&self, idx: usize) -> usize {
+        match &self.nodes[idx] {
+            KDTreeNode::Node { len, .. } => *len,
+            KDTreeNode::Leaf { len, .. } => *len,
+        }
+    }
+
+    fn leaf(&self, start: usize, len: usize) -> &[([T; DIMS], usize)] {
+        &self.leaf_points[start..start + len]
+    }
+
+    fn collect_points(&self, idx: usize, points: &mut Vec<([T; DIMS], usize)>) {
+        match self.nodes[idx] {
+            KDTreeNode::Node { left, right, .. } => {
+                self.collect_points(left, points);
+                self.collect_points(right, points);
+            }
+            KDTreeNode::Leaf { start, len, .. } => points.extend_from_slice(self.leaf(start, len)),
+        }
+    }
+
+    // frees the nodes below `idx` and the slots of every leaf in its subtree, `idx` itself is
+    // about to be replaced
+    fn free_subtree(&mut self, idx: usize) {
+        match self.nodes[idx] {
+            KDTreeNode::Node { left, right, .. } => {
+                self.free_subtree(left);
+                self.free_subtree(right);
+                self.free_nodes.extend([left, right]);
+            }
+            KDTreeNode::Leaf { capacity, .. } => self.unused_slots += capacity,
+        }
+    }
+
+    // replaces the subtree at `idx` by a leaf holding `points`, appended to `leaf_points`
+    fn replace_with_leaf(&mut self, idx: usize, points: &[([T; DIMS], usize)], capacity: usize) {
+        self.free_subtree(idx);
+        let start = self.leaf_points.len();
+        self.leaf_points.extend_from_slice(points);
+        // the spare slots only need some value until they are used
+        if let Some(&filler) = points.first() {
+            self.leaf_points.resize(start + capacity, filler);
+        }
+        self.nodes[idx] = KDTreeNode::Leaf {
+            start,
+            len: points.len(),
+            capacity,
+        };
+        self.compact_if_sparse();
+    }
+
+    // moves the points of all leaves together again once most slots are unused
+    fn compact_if_sparse(&mut self) {
+        if 2 * self.unused_slots <= self.leaf_points.len() {
+            return;
+        }
+        let reachable: Vec<usize> = self.reachable_nodes().iter().map(|&(idx, _)| idx).collect();
+        let mut leaf_points = Vec::with_capacity(self.len());
+        for idx in reachable {
+            if let KDTreeNode::Leaf { start, len, .. } = self.nodes[idx] {
+                self.nodes[idx] = KDTreeNode::Leaf {
+                    start: leaf_points.len(),
+                    len,
+                    capacity: len,
+                };
+                leaf_points.extend_from_slice(self.leaf(start, len));
             }
-            Self::Leaf { areas } => {
-                for &overlapping_area in areas {
-                    if area.overlaps(&overlapping_area) {
-                        return true;
+        }
+        self.leaf_points = leaf_points;
+        self.unused_slots = 0;
+    }
+
+    // rebuilt subtrees split on the exact median, so they come out balanced unless many points
+    // share the median coordinate
+    fn rebuild(&mut self, idx: usize, axis: u32, extra_point: Option<([T; DIMS], usize)>) {
+        let mut points = Vec::with_capacity(self.node_len(idx) + 1);
+        self.collect_points(idx, &mut points);
+        points.extend(extra_point);
+        self.free_subtree(idx);
+        let start = self.leaf_points.len();
+        self.nodes[idx] = self.construct_node(
+            &mut points,
+            &mut PivotPicker::new(PivotStrategy::Median),
+            axis,
+            start,
+        );
+        self.leaf_points.extend(points);
+        self.compact_if_sparse();
+    }
+
+    // rebuilding a stuck subtree gives the same split until its points have changed enough
+    fn may_rebuild(len: usize, stuck_len: usize) -> bool {
+        stuck_len == 0 || len >= 2 * stuck_len || len <= stuck_len / 2
+    }
+
+    fn is_balanced(left_len: usize, right_len: usize) -> bool {
+        let len = left_len + right_len;
+        len <= 2 * KDTREE_MAX_LEAF_SIZE
+            || (left_len.max(right_len) as f64) <= KDTREE_BALANCE_FACTOR * len as f64
+    }
+
+    /// Add a point to the tree, returning its index. Subtrees that become too lopsided are
+    /// rebuilt.
+    pub fn insert(&mut self, point: [T; DIMS]) -> usize {
+        let index = self.points_by_index.len();
+        self.points_by_index.push(Some(point));
+        let mut idx = self.root;
+        let mut leaf_axis = 0;
+        loop {
+            match self.nodes[idx] {
+                KDTreeNode::Node {
+                    left,
+                    right,
+                    pivot,
+                    axis,
+                    ref mut len,
+                    stuck_len,
+                } => {
+                    *len += 1;
+                    let may_rebuild = Self::may_rebuild(*len, stuck_len);
+                    let goes_left = point[axis as usize] < pivot;
+                    let (left_len, right_len) = if goes_left {
+                        (self.node_len(left) + 1, self.node_len(right))
+                    } else {
+                        (self.node_len(left), self.node_len(right) + 1)
+                    };
+                    // going top-down means we rebuild the highest unbalanced subtree on the path
+                    if may_rebuild && !Self::is_balanced(left_len, right_len) {
+                        self.rebuild(idx, axis, Some((point, index)));
+                        return index;
                     }
+                    idx = if goes_left { left } else { right };
+                    leaf_axis = (axis + 1) % (DIMS as u32);
+                }
+                KDTreeNode::Leaf {
+                    start,
+                    ref mut len,
+                    capacity,
+                } => {
+                    // a leaf of identical points can't be split, so don't keep trying
+                    let can_split = *len > 0 && self.leaf_points[start].0 != point;
+                    if *len < capacity {
+                        self.leaf_points[start + *len] = (point, index);
+                        *len += 1;
+                    } else {
+                        // out of slots, so move it to the end with room to grow until it's split
+                        let len = *len;
+                        let mut points = self.leaf(start, len).to_vec();
+                        points.push((point, index));
+                        let capacity = (2 * points.len()).max(KDTREE_MAX_LEAF_SIZE + 1);
+                        self.replace_with_leaf(idx, &points, capacity);
+                    }
+                    if self.node_len(idx) > KDTREE_MAX_LEAF_SIZE && can_split {
+                        self.rebuild(idx, leaf_axis, None);
+                    }
+                    return index;
                 }
-                false
             }
         }
     }
 
-    pub fn get_overlapping(&self, area: Area) -> HashSet<Area> {
-        let mut overlapping_areas = HashSet::new();
-        self.get_overlapping_recur(&mut overlapping_areas, area);
-        overlapping_areas
+    /// Remove one point with the given coordinates, returning its index if it was present.
+    pub fn remove(&mut self, point: &[T; DIMS]) -> Option<usize> {
+        let index = self.remove_recur(self.root, point, None)?;
+        self.points_by_index[index] = None;
+        Some(index)
+    }
+
+    /// Remove the point with the given index, returning its coordinates if it was present.
+    pub fn remove_index(&mut self, index: usize) -> Option<[T; DIMS]> {
+        let point = self.points_by_index.get(index).copied().flatten()?;
+        self.remove_recur(self.root, &point, Some(index))?;
+        self.points_by_index[index] = None;
+        Some(point)
+    }
+
+    /// Coordinates of the point with the given index, if it is in the tree.
+    pub fn get(&self, index: usize) -> Option<&[T; DIMS]> {
+        self.points_by_index.get(index)?.as_ref()
     }
 
-    fn get_overlapping_recur(&self, overlapping_areas: &mut HashSet<Area>, area: Area) {
-        match self {
-            Self::Node {
+    // removes a point with these coordinates, and this index if one is given
+    fn remove_recur(
+        &mut self,
+        idx: usize,
+        point: &[T; DIMS],
+        index: Option<usize>,
+    ) -> Option<usize> {
+        match self.nodes[idx] {
+            KDTreeNode::Node {
                 left,
                 right,
                 pivot,
                 axis,
+                stuck_len,
+                ..
             } => {
-                if area.bottom_left[*axis as usize] < *pivot {
-                    Self::get_overlapping_recur(left, overlapping_areas, area);
-                }
-                if area.top_right[*axis as usize] >= *pivot {
-                    Self::get_overlapping_recur(right, overlapping_areas, area);
+                let child = if point[axis as usize] < pivot {
+                    left
+                } else {
+                    right
+                };
+                let removed = self.remove_recur(child, point, index)?;
+                let len = self.node_len(left) + self.node_len(right);
+                if len <= 8 {
+                    let mut points = Vec::with_capacity(len);
+                    self.collect_points(idx, &mut points);
+                    self.replace_with_leaf(idx, &points, len);
+                } else {
+                    if let KDTreeNode::Node { len: node_len, .. } = &mut self.nodes[idx] {
+                        *node_len = len;
+                    }
+                    if Self::may_rebuild(len, stuck_len)
+                        && !Self::is_balanced(self.node_len(left), self.node_len(right))
+                    {
+                        self.rebuild(idx, axis, None);
+                    }
                 }
+                Some(removed)
             }
-            Self::Leaf { areas } => {
-                for &overlapping_area in areas.iter().filter(|a| area.overlaps(a)) {
-                    overlapping_areas.insert(overlapping_area);
-                }
+            KDTreeNode::Leaf {
+                start, ref mut len, ..
+            } => {
+                let pos = self.leaf_points[start..start + *len]
+                    .iter()
+                    .position(|(p, i)| p == point && index.is_none_or(|index| index == *i))?;
+                *len -= 1;
+                self.leaf_points.swap(start + pos, start + *len);
+                Some(self.leaf_points[start + *len].1)
             }
         }
     }
 
-    pub fn count_overlapping(&self, area: Area) -> usize {
-        self.get_overlapping(area).len()
+    pub fn iter(&self) -> KDTreeIter<'_, T, DIMS> {
+        KDTreeIter {
+            tree: self,
+            stack: smallvec![self.root],
+            leaf: [].iter(),
+        }
     }
 
-    pub fn print(&self) {
-        AreaKDTree::print_recur(self, 0);
+    /// Points inside `bounds`, including its boundary.
+    pub fn within_box(&self, bounds: AaBox<T, DIMS>) -> KDTreeBoxIter<'_, T, DIMS> {
+        KDTreeBoxIter {
+            tree: self,
+            bounds,
+            stack: smallvec![self.root],
+            leaf: [].iter(),
+        }
+    }
+
+    /// Number of points inside `bounds`, including its boundary. Subtrees that lie entirely
+    /// inside it are counted without visiting their points.
+    pub fn count_within_box(&self, bounds: AaBox<T, DIMS>) -> usize {
+        self.count_within_box_recur(self.root, &bounds, [None; DIMS], [None; DIMS])
     }
 
-    fn print_recur(kdtree: &AreaKDTree, indent: usize) {
-        match kdtree {
-            Self::Node {
+    // the cell of a node holds points with `cell_min <= point < cell_max`, `None` is unbounded
+    fn count_within_box_recur(
+        &self,
+        idx: usize,
+        bounds: &AaBox<T, DIMS>,
+        cell_min: [Option<T>; DIMS],
+        cell_max: [Option<T>; DIMS],
+    ) -> usize {
+        let cell_inside = (0..DIMS).all(|axis| {
+            cell_min[axis].is_some_and(|cell_min| bounds.min[axis] <= cell_min)
+                && cell_max[axis].is_some_and(|cell_max| cell_max <= bounds.max[axis])
+        });
+        if cell_inside {
+            return self.node_len(idx);
+        }
+        match self.nodes[idx] {
+            KDTreeNode::Node {
                 left,
                 right,
                 pivot,
                 axis,
+                ..
             } => {
-                println!(
-                    "{} Node, pivot: {}, axis: {}",
-                    " ".repeat(indent),
-                    pivot,
-                    axis
-                );
-                AreaKDTree::print_recur(left, indent + 2);
-                AreaKDTree::print_recur(right, indent + 2);
-            }
-            Self::Leaf { areas } => {
-                println!("{} Leaf {:?}", " ".repeat(indent), areas);
+                let axis = axis as usize;
+                let mut count = 0;
+                if bounds.min[axis] < pivot {
+                    let mut left_max = cell_max;
+                    left_max[axis] = Some(pivot);
+                    count += self.count_within_box_recur(left, bounds, cell_min, left_max);
+                }
+                if bounds.max[axis] >= pivot {
+                    let mut right_min = cell_min;
+                    right_min[axis] = Some(pivot);
+                    count += self.count_within_box_recur(right, bounds, right_min, cell_max);
+                }
+                count
             }
+            KDTreeNode::Leaf { start, len, .. } => self
+                .leaf(start, len)
+                .iter()
+                .filter(|(point, _)| bounds.contains_point(point))
+                .count(),
         }
     }
-}
-
-pub trait ZeroExt {
-    fn zero() -> Self;
-    fn is_zero(&self) -> bool;
-}
-
-pub trait AbsExt {
-    fn abs(self) -> Self;
-}
 
-pub trait NumExt:
-    Mul<Output = Self>
-    + MulAssign
-    + Div<Output = Self>
-    + DivAssign
-    + Add<Output = Self>
-    + AddAssign
-    + Sub<Output = Self>
-    + SubAssign
-    + ZeroExt
-    + AbsExt
-    + Ord
-    + Copy
-{
-}
+    /// All pairs of points, closest first.
+    pub fn closest_pairs(&self) -> ClosestPairs<'_, T, DIMS> {
+        ClosestPairs::with_metric(self, SquaredEuclidean)
+    }
 
-#[derive(Clone, Eq, PartialEq, Debug)]
-pub struct Mat<T> {
-    data: Box<[T]>,
-    cols: usize,
-    rows: usize,
-}
+    pub fn closest_pairs_by<M: Metric<T> + Clone>(
+        &self,
+        metric: M,
+    ) -> ClosestPairs<'_, T, DIMS, M> {
+        ClosestPairs::with_metric(self, metric)
+    }
 
-impl<T> Mat<T>
-where
-    T: NumExt,
-    T: Display,
-{
-    fn div_row_by(&mut self, row: usize, value: T) {
-        for item in &mut self.data[row * self.cols..(row + 1) * self.cols] {
-            *item /= value;
+    /// Edges of a minimum spanning tree (Kruskal), shortest first. If the tree contains `n`
+    /// points there are `n - 1` edges, so the last one is the connection that joins everything
+    /// into a single cluster.
+    pub fn minimum_spanning_tree(&self) -> Vec<(usize, usize, T::Dist)> {
+        let mut sets = DisjointSets::new(self.points_by_index.len());
+        let num_edges = self.len().saturating_sub(1);
+        let mut edges = Vec::with_capacity(num_edges);
+        if num_edges > 0 {
+            for (i, j, dist) in self.closest_pairs() {
+                if sets.union(i, j) {
+                    edges.push((i, j, dist));
+                    if edges.len() == num_edges {
+                        break;
+                    }
+                }
+            }
         }
+        edges
     }
 
-    fn sub_n_rows_from_row(&mut self, row: usize, n: T, row_to_sub: usize) {
-        let row_start = row * self.cols;
-        let row_to_sub_start = row_to_sub * self.cols;
-        for col in 0..self.cols {
-            self.data[row_start + col] -= n * self.data[row_to_sub_start + col];
+    /// Clusters formed by connecting the `k` closest pairs of points, whether or not a pair was
+    /// already in the same cluster. Every point is in exactly one cluster.
+    pub fn clusters_after_pairs(&self, k: usize) -> Vec<Vec<usize>> {
+        let mut sets = DisjointSets::new(self.points_by_index.len());
+        for (i, j, _) in self.closest_pairs().take(k) {
+            sets.union(i, j);
         }
+        sets.groups(self.iter().map(|(i, _)| i))
     }
 
-    fn swap_rows(&mut self, i: usize, j: usize) {
-        let lower = std::cmp::min(i, j);
-        let higher = std::cmp::max(i, j);
-        if higher < self.rows {
-            let (first, second) = self.data.split_at_mut(higher * self.cols);
-            first[lower * self.cols..(lower + 1) * self.cols]
-                .swap_with_slice(&mut second[0..self.cols]);
+    /// Clusters formed by the first `k` merges of single-linkage clustering, i.e. the first `k`
+    /// edges of the minimum spanning tree.
+    pub fn clusters_after_merges(&self, k: usize) -> Vec<Vec<usize>> {
+        let mut sets = DisjointSets::new(self.points_by_index.len());
+        let mut merges = 0;
+        for (i, j, _) in self.closest_pairs() {
+            if merges >= k {
+                break;
+            }
+            if sets.union(i, j) {
+                merges += 1;
+            }
         }
+        sets.groups(self.iter().map(|(i, _)| i))
     }
 
-    fn highest_row_below(&self, start_row: usize, column: usize) -> Option<usize> {
-        let mut cur_val: Option<T> = None;
-        let mut best_row = None;
-        for row in start_row..self.rows {
-            let new_val = self[(row, column)];
-            if !new_val.is_zero() && cur_val.map(|v| new_val.abs() > v.abs()).unwrap_or(true) {
-                cur_val = Some(new_val);
-                best_row = Some(row);
+    pub fn print(&self) {
+        self.print_recur(self.root, 0);
+    }
+
+    fn print_recur(&self, idx: usize, indent: usize) {
+        match &self.nodes[idx] {
+            KDTreeNode::Node {
+                left,
+                right,
+                pivot,
+                axis,
+                ..
+            } => {
+                println!(
+                    "{} Node, pivot: {:?}, axis: {}",
+                    " ".repeat(indent),
+                    pivot,
+                    axis
+                );
+                self.print_recur(*left, indent + 2);
+                self.print_recur(*right, indent + 2);
+            }
+            &KDTreeNode::Leaf { start, len, .. } => {
+                println!("{} Leaf {:?}", " ".repeat(indent), self.leaf(start, len));
             }
         }
-        best_row
     }
 
-    pub fn to_rref(&mut self, augmented_mat: bool) {
-        let mut non_zero_col = 0;
-        let max_cols = if augmented_mat {
-            self.cols.saturating_sub(1)
-        } else {
-            self.cols
-        };
-        // bring each row to ref
-        for i in 0..self.rows {
-            while non_zero_col < max_cols {
-                // if we found some non-zero row
-                if let Some(row) = self.highest_row_below(i, non_zero_col) {
-                    if row != i {
-                        self.swap_rows(i, row);
-                    }
-                    // normalize the row
-                    let val = self[(i, non_zero_col)];
-                    self.div_row_by(i, val);
+    pub fn stats(&self) -> TreeStats {
+        let mut stats = TreeStats::default();
+        self.stats_recur(self.root, 0, &mut stats);
+        stats
+    }
 
-                    // eliminate all other rows
-                    for other_row in 0..self.rows {
-                        if other_row != i {
-                            let val = self[(other_row, non_zero_col)];
-                            if !val.is_zero() {
-                                self.sub_n_rows_from_row(other_row, val, i);
-                            }
-                        }
-                    }
+    fn stats_recur(&self, idx: usize, depth: usize, stats: &mut TreeStats) {
+        match self.nodes[idx] {
+            KDTreeNode::Node { left, right, .. } => {
+                stats.add_node(self.node_len(left), self.node_len(right));
+                self.stats_recur(left, depth + 1, stats);
+                self.stats_recur(right, depth + 1, stats);
+            }
+            KDTreeNode::Leaf { len, .. } => stats.add_leaf(depth, len),
+        }
+    }
 
-                    non_zero_col += 1;
-                    break;
+    /// Graphviz DOT description of the tree. Leaves only show how many points they hold.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph kdtree {\n");
+        for (idx, node) in self.reachable_nodes() {
+            match node {
+                KDTreeNode::Node {
+                    left,
+                    right,
+                    pivot,
+                    axis,
+                    len,
+                    ..
+                } => {
+                    dot += &format!(
+                        "  n{idx} [label=\"axis {axis} pivot {pivot:?}, {len} points\"];\n"
+                    );
+                    dot += &format!("  n{idx} -> n{left} [label=\"<\"];\n");
+                    dot += &format!("  n{idx} -> n{right} [label=\">=\"];\n");
+                }
+                KDTreeNode::Leaf { len, .. } => {
+                    dot += &format!("  n{idx} [shape=box, label=\"{len} points\"];\n");
                 }
-                non_zero_col += 1;
             }
         }
+        dot += "}\n";
+        dot
     }
-}
 
-impl<T: Default> Mat<T> {
-    pub fn new(cols: usize, rows: usize) -> Mat<T> {
-        let mut data = Vec::new();
-        data.resize_with(cols * rows, T::default);
-        Mat {
-            data: data.into_boxed_slice(),
-            cols,
-            rows,
+    /// Nested JSON description of the tree, including every point and its index.
+    pub fn to_json(&self) -> JsonValue {
+        self.to_json_recur(self.root)
+    }
+
+    fn to_json_recur(&self, idx: usize) -> JsonValue {
+        match self.nodes[idx] {
+            KDTreeNode::Node {
+                left,
+                right,
+                pivot,
+                axis,
+                len,
+                ..
+            } => json_object([
+                ("axis", JsonValue::Number(axis as f64)),
+                ("pivot", pivot.to_json()),
+                ("len", JsonValue::Number(len as f64)),
+                ("left", self.to_json_recur(left)),
+                ("right", self.to_json_recur(right)),
+            ]),
+            KDTreeNode::Leaf { start, len, .. } => json_object([(
+                "points",
+                JsonValue::Array(
+                    self.leaf(start, len)
+                        .iter()
+                        .map(|(point, index)| {
+                            json_object([
+                                ("index", JsonValue::Number(*index as f64)),
+                                ("point", json_coords(point)),
+                            ])
+                        })
+                        .collect(),
+                ),
+            )]),
         }
     }
-}
 
-impl<T> Mat<T> {
-    pub fn rows(&self) -> usize {
-        self.rows
+    // nodes in the free list are left over from rebuilds and are not part of the tree
+    fn reachable_nodes(&self) -> Vec<(usize, &KDTreeNode<T>)> {
+        let mut nodes = Vec::new();
+        let mut stack = vec![self.root];
+        while let Some(idx) = stack.pop() {
+            let node = &self.nodes[idx];
+            if let &KDTreeNode::Node { left, right, .. } = node {
+                stack.extend([right, left]);
+            }
+            nodes.push((idx, node));
+        }
+        nodes
     }
-    pub fn cols(&self) -> usize {
-        self.cols
+
+    pub fn iter_nearest<'a>(&'a self, to: [T; DIMS]) -> KDTreeNearestIter<'a, T, DIMS> {
+        self.iter_nearest_by(to, SquaredEuclidean)
     }
-    pub fn row(&self, row: usize) -> &[T] {
-        if row >= self.rows {
-            panic!("index out of bounds");
-        }
-        &self.data[row * self.cols..(row + 1) * self.cols]
+
+    pub fn iter_nearest_by<'a, M: Metric<T>>(
+        &'a self,
+        to: [T; DIMS],
+        metric: M,
+    ) -> KDTreeNearestIter<'a, T, DIMS, M> {
+        KDTreeNearestIter::with_metric(self, to, metric)
     }
-}
 
-impl<T: Clone> Mat<T> {
-    pub fn from_array<const R: usize, const C: usize>(array: &[[T; C]; R]) -> Mat<T> {
-        let mut data = Vec::with_capacity(R * C);
-        for row in array {
-            data.extend_from_slice(row);
-        }
-        Mat {
-            data: data.into_boxed_slice(),
-            cols: C,
-            rows: R,
-        }
+    /// The `k` points closest to `to`, closest first.
+    pub fn k_nearest(&self, to: [T; DIMS], k: usize) -> Vec<Neighbour<'_, T, DIMS>> {
+        self.k_nearest_by(to, k, SquaredEuclidean)
     }
-    pub fn with_value(cols: usize, rows: usize, val: T) -> Mat<T> {
-        Mat {
-            data: vec![val; cols * rows].into_boxed_slice(),
-            cols,
-            rows,
-        }
+
+    pub fn k_nearest_by<M: Metric<T>>(
+        &self,
+        to: [T; DIMS],
+        k: usize,
+        metric: M,
+    ) -> Vec<Neighbour<'_, T, DIMS>> {
+        self.k_nearest_filtered(to, k, &metric, |_, _| true)
     }
-}
 
-impl<T> Index<(usize, usize)> for Mat<T> {
-    type Output = T;
+    /// The point closest to `to` that does not have the same coordinates as `to`.
+    pub fn nearest_excluding(&self, to: [T; DIMS]) -> Option<Neighbour<'_, T, DIMS>> {
+        self.nearest_excluding_by(to, SquaredEuclidean)
+    }
 
-    fn index(&self, index: (usize, usize)) -> &T {
-        if index.0 < self.rows && index.1 < self.cols {
-            &self.data[index.0 * self.cols + index.1]
-        } else {
-            panic!("out of bounds!");
-        }
+    pub fn nearest_excluding_by<M: Metric<T>>(
+        &self,
+        to: [T; DIMS],
+        metric: M,
+    ) -> Option<Neighbour<'_, T, DIMS>> {
+        self.k_nearest_filtered(to, 1, &metric, |_, point| *point != to)
+            .pop()
     }
-}
 
-impl<T> IndexMut<(usize, usize)> for Mat<T> {
-    fn index_mut(&mut self, index: (usize, usize)) -> &mut T {
-        if index.0 < self.rows && index.1 < self.cols {
-            &mut self.data[index.0 * self.cols + index.1]
-        } else {
-            panic!("out of bounds!");
+    /// The point closest to the point with the given index, other than that point itself.
+    /// Unlike `nearest_excluding`, another point with the same coordinates is a valid answer.
+    pub fn nearest_excluding_index(&self, index: usize) -> Option<Neighbour<'_, T, DIMS>> {
+        self.nearest_excluding_index_by(index, SquaredEuclidean)
+    }
+
+    pub fn nearest_excluding_index_by<M: Metric<T>>(
+        &self,
+        index: usize,
+        metric: M,
+    ) -> Option<Neighbour<'_, T, DIMS>> {
+        let to = *self.get(index)?;
+        self.k_nearest_filtered(to, 1, &metric, |i, _| i != index)
+            .pop()
+    }
+
+    /// All points whose distance to `to` is at most `max_dist`, closest first. The distance is
+    /// measured the same way as `points_dist`, so this is the squared radius.
+    pub fn within_radius(&self, to: [T; DIMS], max_dist: T::Dist) -> Vec<Neighbour<'_, T, DIMS>> {
+        self.within_radius_by(to, max_dist, SquaredEuclidean)
+    }
+
+    pub fn within_radius_by<M: Metric<T>>(
+        &self,
+        to: [T; DIMS],
+        max_dist: T::Dist,
+        metric: M,
+    ) -> Vec<Neighbour<'_, T, DIMS>> {
+        let mut result = Vec::new();
+        self.within_radius_recur(self.root, &to, max_dist, &metric, &mut result);
+        result.sort_by(|a, b| cmp_coords(&a.2, &b.2));
+        result
+    }
+
+    fn k_nearest_filtered<M: Metric<T>, F: Fn(usize, &[T; DIMS]) -> bool>(
+        &self,
+        to: [T; DIMS],
+        k: usize,
+        metric: &M,
+        filter: F,
+    ) -> Vec<Neighbour<'_, T, DIMS>> {
+        if k == 0 {
+            return Vec::new();
         }
+        let mut nearest_items = BinaryHeap::with_capacity(k + 1);
+        self.k_nearest_recur(self.root, &to, k, metric, &filter, &mut nearest_items);
+        nearest_items
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(i)| (i.index, i.item, i.dist))
+            .collect()
     }
-}
 
-impl<T> Display for Mat<T>
-where
-    T: Display,
-{
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if self.rows > 0 {
-            for row in 0..self.rows {
-                f.write_str(if row == 0 { "[" } else { " " })?;
-                for col in 0..self.cols {
-                    if col > 0 {
-                        write!(f, ", ")?;
+    // nearest_items is a max-heap of at most k items, so the furthest candidate is on top
+    fn k_nearest_recur<'a, M: Metric<T>, F: Fn(usize, &[T; DIMS]) -> bool>(
+        &'a self,
+        idx: usize,
+        to: &[T; DIMS],
+        k: usize,
+        metric: &M,
+        filter: &F,
+        nearest_items: &mut BinaryHeap<Reverse<KDTreeNearestIterHeapItem<'a, T, DIMS>>>,
+    ) {
+        match self.nodes[idx] {
+            KDTreeNode::Node {
+                left,
+                right,
+                pivot,
+                axis,
+                ..
+            } => {
+                let (near, far) = if to[axis as usize] < pivot {
+                    (left, right)
+                } else {
+                    (right, left)
+                };
+                self.k_nearest_recur(near, to, k, metric, filter, nearest_items);
+                if nearest_items.len() < k
+                    || metric.axis_bound(pivot.axis_dist(to[axis as usize]))
+                        <= nearest_items.peek().unwrap().0.dist
+                {
+                    self.k_nearest_recur(far, to, k, metric, filter, nearest_items);
+                }
+            }
+            KDTreeNode::Leaf { start, len, .. } => {
+                let points = self.leaf(start, len);
+                for (point, index) in points.iter().filter(|(point, index)| filter(*index, point)) {
+                    let item = KDTreeNearestIterHeapItem {
+                        index: *index,
+                        item: point,
+                        dist: metric.dist(to, point),
+                    };
+                    if nearest_items.len() < k {
+                        nearest_items.push(Reverse(item));
+                    } else if let Some(mut furthest) = nearest_items.peek_mut()
+                        && item.dist < furthest.0.dist
+                    {
+                        // replacing the top sifts once, instead of once to pop and once to push
+                        *furthest = Reverse(item);
                     }
-                    self[(row, col)].fmt(f)?;
                 }
+            }
+        }
+    }
 
-                f.write_str(if row == self.rows - 1 { "]" } else { "\n" })?;
+    fn within_radius_recur<'a, M: Metric<T>>(
+        &'a self,
+        idx: usize,
+        to: &[T; DIMS],
+        max_dist: T::Dist,
+        metric: &M,
+        result: &mut Vec<Neighbour<'a, T, DIMS>>,
+    ) {
+        match self.nodes[idx] {
+            KDTreeNode::Node {
+                left,
+                right,
+                pivot,
+                axis,
+                ..
+            } => {
+                let (near, far) = if to[axis as usize] < pivot {
+                    (left, right)
+                } else {
+                    (right, left)
+                };
+                self.within_radius_recur(near, to, max_dist, metric, result);
+                if metric.axis_bound(pivot.axis_dist(to[axis as usize])) <= max_dist {
+                    self.within_radius_recur(far, to, max_dist, metric, result);
+                }
+            }
+            KDTreeNode::Leaf { start, len, .. } => {
+                for (point, index) in self.leaf(start, len) {
+                    let dist = metric.dist(to, point);
+                    if dist <= max_dist {
+                        result.push((*index, point, dist));
+                    }
+                }
             }
         }
-        Ok(())
     }
 }
 
-#[derive(PartialEq, Eq, Clone, Copy, Debug)]
-pub struct Rational {
-    numerator: i32,
-    denominator: i32,
+#[derive(Debug)]
+struct KDTreeNearestIterHeapItem<'a, T: Coordinate, const DIMS: usize> {
+    index: usize,
+    item: &'a [T; DIMS],
+    dist: T::Dist,
 }
 
-impl Rational {
-    pub fn new<T: Into<i32>>(numerator: T, denominator: T) -> Rational {
-        Rational {
-            numerator: numerator.into(),
-            denominator: denominator.into(),
-        }
+impl<'a, T: Coordinate, const DIMS: usize> Ord for KDTreeNearestIterHeapItem<'a, T, DIMS> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        cmp_coords(&other.dist, &self.dist)
     }
+}
 
-    pub fn from_int<T: Into<i32>>(i: T) -> Rational {
-        Rational {
-            numerator: i.into(),
-            denominator: 1,
-        }
+impl<'a, T: Coordinate, const DIMS: usize> PartialOrd for KDTreeNearestIterHeapItem<'a, T, DIMS> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
     }
+}
 
-    pub fn to_int(self) -> Option<i32> {
-        if self.denominator == 1 {
-            Some(self.numerator)
-        } else {
-            None
-        }
+impl<'a, T: Coordinate, const DIMS: usize> PartialEq for KDTreeNearestIterHeapItem<'a, T, DIMS> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
     }
+}
 
-    fn normalize(&mut self) {
-        if self.denominator == 0 {
-            self.numerator = 0;
-            self.denominator = 1;
-            return;
-        }
-        if self.denominator < 0 {
-            self.numerator = -self.numerator;
-            self.denominator = -self.denominator;
-        }
+impl<'a, T: Coordinate, const DIMS: usize> Eq for KDTreeNearestIterHeapItem<'a, T, DIMS> {}
 
-        let gcd = gcd(self.numerator.abs(), self.denominator);
-        self.numerator /= gcd;
-        self.denominator /= gcd;
-    }
+// a subtree that hasn't been visited yet, with a lower bound on the distance to any point in it
+#[derive(Debug)]
+struct KDTreeNearestIterBranch<T: Coordinate> {
+    idx: usize,
+    bound: T::Dist,
 }
 
-impl From<Rational> for f64 {
-    fn from(value: Rational) -> Self {
-        value.numerator as f64 / value.denominator as f64
+impl<T: Coordinate> Ord for KDTreeNearestIterBranch<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        cmp_coords(&other.bound, &self.bound)
     }
 }
 
-impl From<Rational> for f32 {
-    fn from(value: Rational) -> Self {
-        value.numerator as f32 / value.denominator as f32
+impl<T: Coordinate> PartialOrd for KDTreeNearestIterBranch<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
     }
 }
 
-impl From<i32> for Rational {
-    fn from(value: i32) -> Self {
-        Rational {
-            numerator: value,
-            denominator: 1,
-        }
+impl<T: Coordinate> PartialEq for KDTreeNearestIterBranch<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
     }
 }
 
-impl Default for Rational {
-    fn default() -> Self {
-        Rational {
-            numerator: 0,
-            denominator: 1,
-        }
+impl<T: Coordinate> Eq for KDTreeNearestIterBranch<T> {}
+
+#[derive(Debug)]
+pub struct KDTreeNearestIter<'a, T: Coordinate, const DIMS: usize, M = SquaredEuclidean> {
+    tree: &'a KDTree<T, DIMS>,
+    target: [T; DIMS],
+    metric: M,
+    nearest_items: BinaryHeap<KDTreeNearestIterHeapItem<'a, T, DIMS>>,
+    pruned_branches: BinaryHeap<KDTreeNearestIterBranch<T>>,
+}
+
+impl<'a, T: Coordinate, const DIMS: usize> KDTreeNearestIter<'a, T, DIMS> {
+    pub fn new(tree: &'a KDTree<T, DIMS>, point: [T; DIMS]) -> KDTreeNearestIter<'a, T, DIMS> {
+        KDTreeNearestIter::with_metric(tree, point, SquaredEuclidean)
     }
 }
 
-impl AddAssign for Rational {
-    fn add_assign(&mut self, rhs: Self) {
-        self.numerator = self.numerator * rhs.denominator + rhs.numerator * self.denominator;
-        self.denominator *= rhs.denominator;
-        self.normalize();
+impl<'a, T: Coordinate, const DIMS: usize, M: Metric<T>> KDTreeNearestIter<'a, T, DIMS, M> {
+    fn walk_to_nearest(&mut self, mut idx: usize) {
+        loop {
+            match self.tree.nodes[idx] {
+                KDTreeNode::Node {
+                    left,
+                    right,
+                    pivot,
+                    axis,
+                    ..
+                } => {
+                    let (near, far) = if self.target[axis as usize] < pivot {
+                        (left, right)
+                    } else {
+                        (right, left)
+                    };
+                    self.pruned_branches.push(KDTreeNearestIterBranch {
+                        idx: far,
+                        bound: self
+                            .metric
+                            .axis_bound(pivot.axis_dist(self.target[axis as usize])),
+                    });
+                    idx = near;
+                }
+                KDTreeNode::Leaf { start, len, .. } => {
+                    for (near_point, index) in self.tree.leaf(start, len) {
+                        self.nearest_items.push(KDTreeNearestIterHeapItem {
+                            index: *index,
+                            item: near_point,
+                            dist: self.metric.dist(&self.target, near_point),
+                        });
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
+    pub fn with_metric(
+        tree: &'a KDTree<T, DIMS>,
+        point: [T; DIMS],
+        metric: M,
+    ) -> KDTreeNearestIter<'a, T, DIMS, M> {
+        let mut result = KDTreeNearestIter {
+            tree,
+            target: point,
+            metric,
+            // room for a few leaves and a path from the root, so short walks never regrow them
+            nearest_items: BinaryHeap::with_capacity(32),
+            pruned_branches: BinaryHeap::with_capacity(32),
+        };
+        result.walk_to_nearest(tree.root);
+        result
+    }
+}
+
+impl<'a, T: Coordinate, const DIMS: usize, M: Metric<T>> Iterator
+    for KDTreeNearestIter<'a, T, DIMS, M>
+{
+    type Item = Neighbour<'a, T, DIMS>;
+
+    fn next(&mut self) -> Option<Neighbour<'a, T, DIMS>> {
+        // expand unvisited subtrees until none of them can hold anything closer than the best item
+        while let Some(branch) = self.pruned_branches.peek()
+            && self
+                .nearest_items
+                .peek()
+                .is_none_or(|nearest| branch.bound <= nearest.dist)
+        {
+            let idx = branch.idx;
+            self.pruned_branches.pop();
+            self.walk_to_nearest(idx);
+        }
+        self.nearest_items.pop().map(|i| (i.index, i.item, i.dist))
+    }
+}
+
+/// Iterator over all points stored in a `KDTree`, with their indices, in no particular order.
+#[derive(Debug)]
+pub struct KDTreeIter<'a, T, const DIMS: usize> {
+    tree: &'a KDTree<T, DIMS>,
+    stack: SmallVec<[usize; 32]>,
+    leaf: std::slice::Iter<'a, ([T; DIMS], usize)>,
+}
+
+impl<'a, T, const DIMS: usize> Iterator for KDTreeIter<'a, T, DIMS> {
+    type Item = (usize, &'a [T; DIMS]);
+
+    fn next(&mut self) -> Option<(usize, &'a [T; DIMS])> {
+        loop {
+            if let Some((point, index)) = self.leaf.next() {
+                return Some((*index, point));
+            }
+            match &self.tree.nodes[self.stack.pop()?] {
+                KDTreeNode::Node { left, right, .. } => self.stack.extend([*right, *left]),
+                &KDTreeNode::Leaf { start, len, .. } => {
+                    self.leaf = self.tree.leaf_points[start..start + len].iter()
+                }
+            }
+        }
+    }
+}
+
+/// Iterator over the points of a `KDTree` inside an axis-aligned box, with their indices.
+#[derive(Debug)]
+pub struct KDTreeBoxIter<'a, T, const DIMS: usize> {
+    tree: &'a KDTree<T, DIMS>,
+    bounds: AaBox<T, DIMS>,
+    stack: SmallVec<[usize; 32]>,
+    leaf: std::slice::Iter<'a, ([T; DIMS], usize)>,
+}
+
+impl<'a, T: Coordinate, const DIMS: usize> Iterator for KDTreeBoxIter<'a, T, DIMS> {
+    type Item = (usize, &'a [T; DIMS]);
+
+    fn next(&mut self) -> Option<(usize, &'a [T; DIMS])> {
+        loop {
+            for (point, index) in self.leaf.by_ref() {
+                if self.bounds.contains_point(point) {
+                    return Some((*index, point));
+                }
+            }
+            match self.tree.nodes[self.stack.pop()?] {
+                KDTreeNode::Node {
+                    left,
+                    right,
+                    pivot,
+                    axis,
+                    ..
+                } => {
+                    if self.bounds.max[axis as usize] >= pivot {
+                        self.stack.push(right);
+                    }
+                    if self.bounds.min[axis as usize] < pivot {
+                        self.stack.push(left);
+                    }
+                }
+                KDTreeNode::Leaf { start, len, .. } => {
+                    self.leaf = self.tree.leaf(start, len).iter()
+                }
+            }
+        }
+    }
+}
+
+struct ClosestPairsCandidate<'a, T: Coordinate, const DIMS: usize, M> {
+    dist: T::Dist,
+    source: usize,
+    nearest: usize,
+    nearest_iter: KDTreeNearestIter<'a, T, DIMS, M>,
+}
+
+impl<'a, T: Coordinate, const DIMS: usize, M> Ord for ClosestPairsCandidate<'a, T, DIMS, M> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        cmp_coords(&other.dist, &self.dist)
+    }
+}
+
+impl<'a, T: Coordinate, const DIMS: usize, M> PartialOrd for ClosestPairsCandidate<'a, T, DIMS, M> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a, T: Coordinate, const DIMS: usize, M> PartialEq for ClosestPairsCandidate<'a, T, DIMS, M> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl<'a, T: Coordinate, const DIMS: usize, M> Eq for ClosestPairsCandidate<'a, T, DIMS, M> {}
+
+/// Iterator over every pair of points in a `KDTree`, closest pair first, as
+/// `(index, other index, distance)` with `index < other index`. Each pair is produced once.
+pub struct ClosestPairs<'a, T: Coordinate, const DIMS: usize, M = SquaredEuclidean> {
+    candidates: BinaryHeap<ClosestPairsCandidate<'a, T, DIMS, M>>,
+}
+
+impl<'a, T: Coordinate, const DIMS: usize, M: Metric<T> + Clone> ClosestPairs<'a, T, DIMS, M> {
+    pub fn with_metric(tree: &'a KDTree<T, DIMS>, metric: M) -> ClosestPairs<'a, T, DIMS, M> {
+        let candidates = tree
+            .iter()
+            .filter_map(|(source, point)| {
+                Self::next_candidate(source, tree.iter_nearest_by(*point, metric.clone()))
+            })
+            .collect();
+        ClosestPairs { candidates }
+    }
+
+    // every point only pairs up with points that have a higher index, so pairs aren't repeated
+    fn next_candidate(
+        source: usize,
+        mut nearest_iter: KDTreeNearestIter<'a, T, DIMS, M>,
+    ) -> Option<ClosestPairsCandidate<'a, T, DIMS, M>> {
+        nearest_iter
+            .find(|&(nearest, _, _)| nearest > source)
+            .map(|(nearest, _, dist)| ClosestPairsCandidate {
+                dist,
+                source,
+                nearest,
+                nearest_iter,
+            })
+    }
+}
+
+impl<'a, T: Coordinate, const DIMS: usize, M: Metric<T> + Clone> Iterator
+    for ClosestPairs<'a, T, DIMS, M>
+{
+    type Item = (usize, usize, T::Dist);
+
+    fn next(&mut self) -> Option<(usize, usize, T::Dist)> {
+        let ClosestPairsCandidate {
+            dist,
+            source,
+            nearest,
+            nearest_iter,
+        } = self.candidates.pop()?;
+        if let Some(candidate) = Self::next_candidate(source, nearest_iter) {
+            self.candidates.push(candidate);
+        }
+        Some((source, nearest, dist))
+    }
+}
+
+/// Union-find over the indices `0..len`, tracking the size of every set.
+#[derive(Debug, Clone)]
+pub struct DisjointSets {
+    parent: Vec<usize>,
+    size: Vec<usize>,
+}
+
+impl DisjointSets {
+    pub fn new(len: usize) -> DisjointSets {
+        DisjointSets {
+            parent: (0..len).collect(),
+            size: vec![1; len],
+        }
+    }
+
+    pub fn find(&mut self, mut idx: usize) -> usize {
+        while self.parent[idx] != idx {
+            // path halving
+            self.parent[idx] = self.parent[self.parent[idx]];
+            idx = self.parent[idx];
+        }
+        idx
+    }
+
+    /// Merge the sets containing `a` and `b`, returning false if they already were the same set.
+    pub fn union(&mut self, a: usize, b: usize) -> bool {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return false;
+        }
+        let (larger, smaller) = if self.size[a] >= self.size[b] {
+            (a, b)
+        } else {
+            (b, a)
+        };
+        self.parent[smaller] = larger;
+        self.size[larger] += self.size[smaller];
+        true
+    }
+
+    pub fn set_size(&mut self, idx: usize) -> usize {
+        let root = self.find(idx);
+        self.size[root]
+    }
+
+    /// The members of every set containing at least one of `indices`.
+    pub fn groups(&mut self, indices: impl IntoIterator<Item = usize>) -> Vec<Vec<usize>> {
+        let mut group_by_root = HashMap::new();
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        for idx in indices {
+            let root = self.find(idx);
+            let group = *group_by_root.entry(root).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[group].push(idx);
+        }
+        groups
+    }
+}
+
+/// Whether boxes that only share part of their boundary count as overlapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Overlap {
+    /// Boxes overlap if they have any point in common, including touching boundaries.
+    #[default]
+    Inclusive,
+    /// Boxes only overlap if their interiors intersect.
+    Exclusive,
+}
+
+/// An axis-aligned box, containing every point with `min <= point <= max` on each axis.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct AaBox<T, const DIMS: usize> {
+    pub min: [T; DIMS],
+    pub max: [T; DIMS],
+}
+
+/// A box over 2D grid positions.
+pub type Area = AaBox<u64, 2>;
+
+impl<T: Coordinate, const DIMS: usize> AaBox<T, DIMS> {
+    /// Box with the given smallest and largest corner. Panics if `min` is above `max` on any
+    /// axis.
+    pub fn new(min: [T; DIMS], max: [T; DIMS]) -> Self {
+        assert!(
+            (0..DIMS).all(|axis| min[axis] <= max[axis]),
+            "box corners out of order: {:?} > {:?}",
+            min,
+            max
+        );
+        AaBox { min, max }
+    }
+
+    /// Smallest box containing both points, which can be any two opposite corners.
+    pub fn from_corners(p1: [T; DIMS], p2: [T; DIMS]) -> Self {
+        let mut min = p1;
+        let mut max = p2;
+        for axis in 0..DIMS {
+            if p2[axis] < p1[axis] {
+                min[axis] = p2[axis];
+                max[axis] = p1[axis];
+            }
+        }
+        AaBox { min, max }
+    }
+
+    pub fn contains_point(&self, point: &[T; DIMS]) -> bool {
+        (0..DIMS).all(|axis| self.min[axis] <= point[axis] && point[axis] <= self.max[axis])
+    }
+
+    pub fn contains(&self, other: &Self) -> bool {
+        self.contains_point(&other.min) && self.contains_point(&other.max)
+    }
+
+    pub fn overlaps(&self, other: &Self, mode: Overlap) -> bool {
+        (0..DIMS).all(|axis| match mode {
+            Overlap::Inclusive => {
+                self.min[axis] <= other.max[axis] && other.min[axis] <= self.max[axis]
+            }
+            Overlap::Exclusive => {
+                self.min[axis] < other.max[axis] && other.min[axis] < self.max[axis]
+            }
+        })
+    }
+
+    /// The points both boxes have in common, if any. Boxes that only touch intersect in a box
+    /// that is flat along some axis.
+    pub fn intersection(&self, other: &Self) -> Option<Self> {
+        if !self.overlaps(other, Overlap::Inclusive) {
+            return None;
+        }
+        let mut intersection = *self;
+        for axis in 0..DIMS {
+            if other.min[axis] > self.min[axis] {
+                intersection.min[axis] = other.min[axis];
+            }
+            if other.max[axis] < self.max[axis] {
+                intersection.max[axis] = other.max[axis];
+            }
+        }
+        Some(intersection)
+    }
+
+    /// Smallest box containing both boxes.
+    pub fn union(&self, other: &Self) -> Self {
+        let mut union = *self;
+        for axis in 0..DIMS {
+            if other.min[axis] < self.min[axis] {
+                union.min[axis] = other.min[axis];
+            }
+            if other.max[axis] > self.max[axis] {
+                union.max[axis] = other.max[axis];
+            }
+        }
+        union
+    }
+
+    /// Product of the box's extents along each axis, so a box that is flat along any axis has
+    /// volume zero, `None` if that overflows. For the number of integer points inside, see
+    /// `lattice_points`.
+    pub fn volume(&self) -> Option<T::Dist> {
+        let mut extents = (0..DIMS).map(|axis| self.min[axis].axis_dist(self.max[axis]));
+        let first = extents.next().expect("zero-dimensional box has no volume");
+        extents.try_fold(first, T::checked_mul_dist)
+    }
+}
+
+impl<T: Coordinate + Into<i128>, const DIMS: usize> AaBox<T, DIMS> {
+    /// Number of integer points inside the box, including those on its boundary, `None` if that
+    /// doesn't fit a `u128`.
+    pub fn lattice_points(&self) -> Option<u128> {
+        (0..DIMS)
+            .map(|axis| (self.max[axis].into() - self.min[axis].into()) as u128 + 1)
+            .try_fold(1u128, |points, extent| points.checked_mul(extent))
+    }
+}
+
+/// Segment tree over elementary intervals, tracking how much of them is covered at least
+/// `1..=k` times while intervals are added and removed.
+struct CoverageTree {
+    // elementary interval `i` is `bounds[i]..bounds[i + 1]`
+    bounds: Vec<u128>,
+    k: usize,
+    // `k + 2` values per node, kept together since they are always used together:
+    // - the number of intervals covering all of the node without being passed on to children
+    // - the length of the node
+    // - for each `t` in `1..=k`, the length of the node covered at least `t` times, only
+    //   counting intervals stored in its subtree
+    nodes: Vec<u128>,
+}
+
+impl CoverageTree {
+    fn new(bounds: Vec<u128>, k: usize) -> CoverageTree {
+        // children of a node over `lo..hi` are at `node + 1` and `node + 2 * (mid - lo)`,
+        // which needs `2 * (hi - lo) - 1` nodes
+        let num_nodes = (2 * bounds.len()).saturating_sub(3).max(1);
+        let mut tree = CoverageTree {
+            bounds,
+            k,
+            nodes: vec![0; num_nodes * (k + 2)],
+        };
+        if tree.num_intervals() > 0 {
+            tree.init_len(0, 0, tree.num_intervals());
+        }
+        tree
+    }
+
+    fn init_len(&mut self, node: usize, lo: usize, hi: usize) {
+        self.nodes[node * (self.k + 2) + 1] = self.bounds[hi] - self.bounds[lo];
+        if hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            self.init_len(node + 1, lo, mid);
+            self.init_len(node + 2 * (mid - lo), mid, hi);
+        }
+    }
+
+    fn num_intervals(&self) -> usize {
+        self.bounds.len().saturating_sub(1)
+    }
+
+    /// Length covered at least `k` times.
+    fn covered(&self) -> u128 {
+        if self.num_intervals() == 0 {
+            0
+        } else {
+            self.nodes[self.k + 1]
+        }
+    }
+
+    /// Index of a value in `bounds`.
+    fn index(&self, bound: u128) -> usize {
+        self.bounds.partition_point(|&b| b < bound)
+    }
+
+    /// Add or remove an interval covering the elementary intervals `start..end`.
+    fn update(&mut self, start: usize, end: usize, add: bool) {
+        let num_intervals = self.num_intervals();
+        self.update_recur(0, 0, num_intervals, start, end, add);
+    }
+
+    fn update_recur(
+        &mut self,
+        node: usize,
+        lo: usize,
+        hi: usize,
+        start: usize,
+        end: usize,
+        add: bool,
+    ) {
+        if end <= lo || hi <= start {
+            return;
+        }
+        let stride = self.k + 2;
+        let mid = lo + (hi - lo) / 2;
+        let (left, right) = (node + 1, node + 2 * (mid - lo));
+        if start <= lo && hi <= end {
+            if add {
+                self.nodes[node * stride] += 1;
+            } else {
+                self.nodes[node * stride] -= 1;
+            }
+        } else {
+            self.update_recur(left, lo, mid, start, end, add);
+            self.update_recur(right, mid, hi, start, end, add);
+        }
+
+        let count = self.nodes[node * stride] as usize;
+        let len = self.nodes[node * stride + 1];
+        for t in 1..=self.k {
+            self.nodes[node * stride + t + 1] = if t <= count {
+                len
+            } else if hi - lo == 1 {
+                0
+            } else {
+                let below = t - count + 1;
+                self.nodes[left * stride + below] + self.nodes[right * stride + below]
+            };
+        }
+    }
+}
+
+/// Number of grid points covered by at least one of the areas.
+pub fn union_area(areas: &[Area]) -> u128 {
+    area_covered_at_least(areas, 1)
+}
+
+/// Number of grid points covered by at least `k` of the areas, using a sweep line over x and
+/// a segment tree over the compressed y coordinates. Panics if `k` is zero or the result is the
+/// entire `u64` plane, which doesn't fit in a `u128`.
+pub fn area_covered_at_least(areas: &[Area], k: usize) -> u128 {
+    // every point is covered at least zero times, which is too many to count
+    assert!(k > 0, "k must be at least 1");
+    // areas are inclusive, so work with half-open bounds, which need one more bit
+    let mut bounds: Vec<u128> = areas
+        .iter()
+        .flat_map(|area| [area.min[1] as u128, area.max[1] as u128 + 1])
+        .collect();
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    let mut tree = CoverageTree::new(bounds, k);
+
+    let mut events: Vec<(u128, bool, usize, usize)> = areas
+        .iter()
+        .flat_map(|area| {
+            let start = tree.index(area.min[1] as u128);
+            let end = tree.index(area.max[1] as u128 + 1);
+            [
+                (area.min[0] as u128, true, start, end),
+                (area.max[0] as u128 + 1, false, start, end),
+            ]
+        })
+        .collect();
+    events.sort_unstable_by_key(|&(x, ..)| x);
+
+    let mut total: u128 = 0;
+    let mut prev_x = 0;
+    for (x, add, start, end) in events {
+        let width = x - prev_x;
+        if width > 0 {
+            let covered = tree.covered().checked_mul(width);
+            total = covered
+                .and_then(|covered| total.checked_add(covered))
+                .expect("covered area doesn't fit in a u128");
+            prev_x = x;
+        }
+        tree.update(start, end, add);
+    }
+    total
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+enum AreaKDTreeNode<T, const DIMS: usize> {
+    Node {
+        left: usize,
+        right: usize,
+        pivot: T,
+        axis: u32,
+    },
+    Leaf {
+        areas: Vec<AaBox<T, DIMS>>,
+    },
+}
+
+/// A k-d tree over boxes. Boxes that straddle a pivot are stored on both sides of it. Like
+/// `KDTree`, nodes live in a single arena and refer to their children by index.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct AreaKDTree<T, const DIMS: usize> {
+    nodes: Vec<AreaKDTreeNode<T, DIMS>>,
+    root: usize,
+    len: usize,
+}
+
+impl<T: Coordinate, const DIMS: usize> AreaKDTree<T, DIMS> {
+    pub fn new(areas: &[AaBox<T, DIMS>]) -> AreaKDTree<T, DIMS> {
+        AreaKDTree::with_pivot_strategy(areas, PivotStrategy::RandomSample)
+    }
+
+    /// Build a tree whose shape only depends on the areas and the seed.
+    pub fn with_seed(areas: &[AaBox<T, DIMS>], seed: u64) -> AreaKDTree<T, DIMS> {
+        AreaKDTree::with_pivot_strategy(areas, PivotStrategy::SeededSample(seed))
+    }
+
+    pub fn with_pivot_strategy(
+        areas: &[AaBox<T, DIMS>],
+        strategy: PivotStrategy,
+    ) -> AreaKDTree<T, DIMS> {
+        let mut tree = AreaKDTree {
+            nodes: Vec::new(),
+            root: 0,
+            len: areas.len(),
+        };
+        tree.root = tree.construct_recur(areas, &mut PivotPicker::new(strategy), 0);
+        tree
+    }
+
+    fn construct_recur(
+        &mut self,
+        areas: &[AaBox<T, DIMS>],
+        picker: &mut PivotPicker,
+        mut axis: u32,
+    ) -> usize {
+        // it's faster to iterate over a small number of elements than to create a deep tree
+        if areas.len() > 8 {
+            // try splitting on every axis
+            for _ in 0..DIMS {
+                let axis_idx = axis as usize;
+                let pivot = picker.pick(areas.len() * 2, |i| {
+                    let area = &areas[i / 2];
+                    if i % 2 == 0 {
+                        area.min[axis_idx]
+                    } else {
+                        area.max[axis_idx]
+                    }
+                });
+                let left: Vec<AaBox<T, DIMS>> = areas
+                    .iter()
+                    .cloned()
+                    .filter(|area| area.min[axis_idx] < pivot)
+                    .collect();
+                let right: Vec<AaBox<T, DIMS>> = areas
+                    .iter()
+                    .cloned()
+                    .filter(|area| area.max[axis_idx] >= pivot)
+                    .collect();
+                let new_axis = (axis + 1) % (DIMS as u32);
+                if left.len() != areas.len() && right.len() != areas.len() {
+                    let node = AreaKDTreeNode::Node {
+                        left: self.construct_recur(&left, picker, new_axis),
+                        right: self.construct_recur(&right, picker, new_axis),
+                        pivot,
+                        axis,
+                    };
+                    self.nodes.push(node);
+                    return self.nodes.len() - 1;
+                }
+                axis = new_axis;
+            }
+        }
+        self.nodes.push(AreaKDTreeNode::Leaf {
+            areas: areas.into(),
+        });
+        self.nodes.len() - 1
+    }
+
+    pub fn any_overlapping(&self, area: &AaBox<T, DIMS>, mode: Overlap) -> bool {
+        self.any_overlapping_recur(self.root, area, mode)
+    }
+
+    fn any_overlapping_recur(&self, idx: usize, area: &AaBox<T, DIMS>, mode: Overlap) -> bool {
+        match &self.nodes[idx] {
+            &AreaKDTreeNode::Node {
+                left,
+                right,
+                pivot,
+                axis,
+            } => {
+                if area.min[axis as usize] < pivot && self.any_overlapping_recur(left, area, mode) {
+                    return true;
+                }
+                if area.max[axis as usize] >= pivot {
+                    return self.any_overlapping_recur(right, area, mode);
+                }
+                false
+            }
+            AreaKDTreeNode::Leaf { areas } => areas.iter().any(|other| area.overlaps(other, mode)),
+        }
+    }
+
+    /// Stored areas overlapping `area`. Every stored area is yielded once, even if it was
+    /// copied into several leaves.
+    pub fn overlapping(
+        &self,
+        area: AaBox<T, DIMS>,
+        mode: Overlap,
+    ) -> AreaKDTreeOverlapIter<'_, T, DIMS> {
+        let root = AreaKDTreeCell {
+            idx: self.root,
+            min: [None; DIMS],
+            max: [None; DIMS],
+        };
+        let mut stack = SmallVec::new();
+        stack.push(root);
+        AreaKDTreeOverlapIter {
+            tree: self,
+            area,
+            mode,
+            stack,
+            leaf: [].iter(),
+            leaf_cell: root,
+        }
+    }
+
+    /// Stored areas containing `point`, including areas that have it on their boundary.
+    pub fn containing_point(&self, point: [T; DIMS]) -> AreaKDTreeOverlapIter<'_, T, DIMS> {
+        self.overlapping(AaBox::new(point, point), Overlap::Inclusive)
+    }
+
+    /// Stored areas that lie entirely inside `bounds`.
+    pub fn within(&self, bounds: AaBox<T, DIMS>) -> impl Iterator<Item = &AaBox<T, DIMS>> {
+        self.overlapping(bounds, Overlap::Inclusive)
+            .filter(move |area| bounds.contains(area))
+    }
+
+    pub fn get_overlapping(&self, area: AaBox<T, DIMS>, mode: Overlap) -> HashSet<AaBox<T, DIMS>>
+    where
+        T: Hash + Eq,
+    {
+        self.overlapping(area, mode).copied().collect()
+    }
+
+    /// Number of stored areas overlapping `area`. Areas that were passed in more than once are
+    /// counted once per copy.
+    pub fn count_overlapping(&self, area: AaBox<T, DIMS>, mode: Overlap) -> usize {
+        self.overlapping(area, mode).count()
+    }
+
+    pub fn print(&self) {
+        self.print_recur(self.root, 0);
+    }
+
+    fn print_recur(&self, idx: usize, indent: usize) {
+        match &self.nodes[idx] {
+            AreaKDTreeNode::Node {
+                left,
+                right,
+                pivot,
+                axis,
+            } => {
+                println!(
+                    "{} Node, pivot: {:?}, axis: {}",
+                    " ".repeat(indent),
+                    pivot,
+                    axis
+                );
+                self.print_recur(*left, indent + 2);
+                self.print_recur(*right, indent + 2);
+            }
+            AreaKDTreeNode::Leaf { areas } => {
+                println!("{} Leaf {:?}", " ".repeat(indent), areas);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn stats(&self) -> TreeStats {
+        let mut stats = TreeStats::default();
+        let stored = self.stats_recur(self.root, 0, &mut stats);
+        stats.duplicated = stored - self.len;
+        stats
+    }
+
+    // returns the number of areas stored in the subtree, counting duplicates
+    fn stats_recur(&self, idx: usize, depth: usize, stats: &mut TreeStats) -> usize {
+        match &self.nodes[idx] {
+            &AreaKDTreeNode::Node { left, right, .. } => {
+                let left_len = self.stats_recur(left, depth + 1, stats);
+                let right_len = self.stats_recur(right, depth + 1, stats);
+                stats.add_node(left_len, right_len);
+                left_len + right_len
+            }
+            AreaKDTreeNode::Leaf { areas } => {
+                stats.add_leaf(depth, areas.len());
+                areas.len()
+            }
+        }
+    }
+
+    /// Graphviz DOT description of the tree. Leaves only show how many areas they hold.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph area_kdtree {\n");
+        for (idx, node) in self.nodes.iter().enumerate() {
+            match node {
+                AreaKDTreeNode::Node {
+                    left,
+                    right,
+                    pivot,
+                    axis,
+                } => {
+                    dot += &format!("  n{idx} [label=\"axis {axis} pivot {pivot:?}\"];\n");
+                    dot += &format!("  n{idx} -> n{left} [label=\"<\"];\n");
+                    dot += &format!("  n{idx} -> n{right} [label=\">=\"];\n");
+                }
+                AreaKDTreeNode::Leaf { areas } => {
+                    let len = areas.len();
+                    dot += &format!("  n{idx} [shape=box, label=\"{len} areas\"];\n");
+                }
+            }
+        }
+        dot += "}\n";
+        dot
+    }
+
+    /// Nested JSON description of the tree, including every area.
+    pub fn to_json(&self) -> JsonValue {
+        self.to_json_recur(self.root)
+    }
+
+    fn to_json_recur(&self, idx: usize) -> JsonValue {
+        match &self.nodes[idx] {
+            &AreaKDTreeNode::Node {
+                left,
+                right,
+                pivot,
+                axis,
+            } => json_object([
+                ("axis", JsonValue::Number(axis as f64)),
+                ("pivot", pivot.to_json()),
+                ("left", self.to_json_recur(left)),
+                ("right", self.to_json_recur(right)),
+            ]),
+            AreaKDTreeNode::Leaf { areas } => json_object([(
+                "areas",
+                JsonValue::Array(
+                    areas
+                        .iter()
+                        .map(|area| {
+                            json_object([
+                                ("min", json_coords(&area.min)),
+                                ("max", json_coords(&area.max)),
+                            ])
+                        })
+                        .collect(),
+                ),
+            )]),
+        }
+    }
+}
+
+// a node of an `AreaKDTree` together with the region of space it covers
+#[derive(Debug, Clone, Copy)]
+struct AreaKDTreeCell<T, const DIMS: usize> {
+    idx: usize,
+    // the node covers `min <= point < max`, `None` is unbounded
+    min: [Option<T>; DIMS],
+    max: [Option<T>; DIMS],
+}
+
+/// Iterator over the areas in an `AreaKDTree` that overlap a query box.
+///
+/// An area straddling a pivot is stored in several leaves. To yield it only once, it is only
+/// reported from the leaf whose cell contains the lowest corner of its intersection with the
+/// query box, which is a point that only one leaf can contain.
+#[derive(Debug)]
+pub struct AreaKDTreeOverlapIter<'a, T, const DIMS: usize> {
+    tree: &'a AreaKDTree<T, DIMS>,
+    area: AaBox<T, DIMS>,
+    mode: Overlap,
+    stack: SmallVec<[AreaKDTreeCell<T, DIMS>; 32]>,
+    leaf: std::slice::Iter<'a, AaBox<T, DIMS>>,
+    leaf_cell: AreaKDTreeCell<T, DIMS>,
+}
+
+impl<'a, T: Coordinate, const DIMS: usize> Iterator for AreaKDTreeOverlapIter<'a, T, DIMS> {
+    type Item = &'a AaBox<T, DIMS>;
+
+    fn next(&mut self) -> Option<&'a AaBox<T, DIMS>> {
+        loop {
+            for other in self.leaf.by_ref() {
+                if other.overlaps(&self.area, self.mode) {
+                    let cell = &self.leaf_cell;
+                    let is_reported_here = (0..DIMS).all(|axis| {
+                        let corner = if other.min[axis] > self.area.min[axis] {
+                            other.min[axis]
+                        } else {
+                            self.area.min[axis]
+                        };
+                        cell.min[axis].is_none_or(|min| min <= corner)
+                            && cell.max[axis].is_none_or(|max| corner < max)
+                    });
+                    if is_reported_here {
+                        return Some(other);
+                    }
+                }
+            }
+            let cell = self.stack.pop()?;
+            match &self.tree.nodes[cell.idx] {
+                &AreaKDTreeNode::Node {
+                    left,
+                    right,
+                    pivot,
+                    axis,
+                } => {
+                    let axis = axis as usize;
+                    if self.area.max[axis] >= pivot {
+                        let mut right_cell = cell;
+                        right_cell.idx = right;
+                        right_cell.min[axis] = Some(pivot);
+                        self.stack.push(right_cell);
+                    }
+                    if self.area.min[axis] < pivot {
+                        let mut left_cell = cell;
+                        left_cell.idx = left;
+                        left_cell.max[axis] = Some(pivot);
+                        self.stack.push(left_cell);
+                    }
+                }
+                AreaKDTreeNode::Leaf { areas } => {
+                    self.leaf = areas.iter();
+                    self.leaf_cell = cell;
+                }
+            }
+        }
+    }
+}
+
+/// Where a point lies relative to a polygon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PointLocation {
+    Inside,
+    OnBoundary,
+    Outside,
+}
+
+/// A simple polygon on the integer grid whose edges are all horizontal or vertical, given by
+/// its vertices in order, either clockwise or counter-clockwise.
+///
+/// The polygon is treated as a closed region of the plane, so points and boxes on its boundary
+/// are inside it.
+#[derive(Debug, Clone)]
+pub struct RectilinearPolygon<T> {
+    vertices: Vec<[T; 2]>,
+    // every edge as a box that is flat along one axis
+    edges: AreaKDTree<T, 2>,
+}
+
+impl<T: Coordinate + Into<i128>> RectilinearPolygon<T> {
+    /// Polygon with the given vertices, or `None` if there are fewer than four of them or two
+    /// consecutive vertices (including the last and the first) aren't on a horizontal or
+    /// vertical line. The polygon is assumed not to intersect itself.
+    pub fn new(vertices: Vec<[T; 2]>) -> Option<RectilinearPolygon<T>> {
+        if vertices.len() < 4 {
+            return None;
+        }
+        let edges: Vec<AaBox<T, 2>> = (0..vertices.len())
+            .map(|i| {
+                let (v1, v2) = (vertices[i], vertices[(i + 1) % vertices.len()]);
+                let axis_aligned = (v1[0] == v2[0]) != (v1[1] == v2[1]);
+                axis_aligned.then(|| AaBox::from_corners(v1, v2))
+            })
+            .collect::<Option<_>>()?;
+        Some(RectilinearPolygon {
+            vertices,
+            edges: AreaKDTree::new(&edges),
+        })
+    }
+
+    pub fn vertices(&self) -> &[[T; 2]] {
+        &self.vertices
+    }
+
+    // consecutive vertices with both coordinates doubled, so midpoints are on the grid too
+    fn doubled_edges(&self) -> impl Iterator<Item = ([i128; 2], [i128; 2])> + '_ {
+        let double = |[x, y]: [T; 2]| [x.into() * 2, y.into() * 2];
+        (0..self.vertices.len()).map(move |i| {
+            (
+                double(self.vertices[i]),
+                double(self.vertices[(i + 1) % self.vertices.len()]),
+            )
+        })
+    }
+
+    /// Area enclosed by the polygon, `None` if it overflows. Uses the shoelace formula, where
+    /// only vertical edges contribute, with `x` measured from the leftmost vertex.
+    pub fn area(&self) -> Option<u128> {
+        let left = self.vertices.iter().map(|&[x, _]| x.into()).min()?;
+        let mut area: i128 = 0;
+        for (i, &[x, y1]) in self.vertices.iter().enumerate() {
+            let [_, y2] = self.vertices[(i + 1) % self.vertices.len()];
+            let edge = (x.into() - left).checked_mul(y2.into() - y1.into())?;
+            area = area.checked_add(edge)?;
+        }
+        Some(area.unsigned_abs())
+    }
+
+    /// Number of grid points on the boundary of the polygon.
+    pub fn boundary_points(&self) -> u128 {
+        self.doubled_edges()
+            .map(|([x1, y1], [x2, y2])| (x1.abs_diff(x2) + y1.abs_diff(y2)) / 2)
+            .sum()
+    }
+
+    /// Number of grid points strictly inside the polygon from Pick's theorem, `None` if the
+    /// area overflows.
+    pub fn interior_points(&self) -> Option<u128> {
+        // the area is at most 2^127, so adding one first can't overflow
+        Some(self.area()? + 1 - self.boundary_points() / 2)
+    }
+
+    /// Number of grid points inside the polygon or on its boundary, `None` if that or the area
+    /// overflows.
+    pub fn lattice_points(&self) -> Option<u128> {
+        self.interior_points()?.checked_add(self.boundary_points())
+    }
+
+    pub fn locate(&self, point: [T; 2]) -> PointLocation {
+        self.locate_doubled([point[0].into() * 2, point[1].into() * 2])
+    }
+
+    pub fn contains_point(&self, point: [T; 2]) -> bool {
+        self.locate(point) != PointLocation::Outside
+    }
+
+    // ray casting towards positive x, only vertical edges can be crossed
+    fn locate_doubled(&self, [x, y]: [i128; 2]) -> PointLocation {
+        let mut inside = false;
+        for ([x1, y1], [x2, y2]) in self.doubled_edges() {
+            let (min_x, max_x) = (x1.min(x2), x1.max(x2));
+            let (min_y, max_y) = (y1.min(y2), y1.max(y2));
+            if (min_x..=max_x).contains(&x) && (min_y..=max_y).contains(&y) {
+                return PointLocation::OnBoundary;
+            }
+            // half-open so that a ray through a vertex is only counted once
+            if x1 == x2 && x1 > x && (min_y..max_y).contains(&y) {
+                inside = !inside;
+            }
+        }
+        if inside {
+            PointLocation::Inside
+        } else {
+            PointLocation::Outside
+        }
+    }
+
+    /// Whether every point of the box is inside the polygon or on its boundary.
+    pub fn contains_box(&self, area: &AaBox<T, 2>) -> bool {
+        // an edge through the interior of the box (or through the middle of a flat box) always
+        // has points outside the polygon right next to it
+        if self.edges.any_overlapping(area, Overlap::Exclusive) {
+            return false;
+        }
+        let [min_x, min_y] = area.min.map(|c| c.into() * 2);
+        let [max_x, max_y] = area.max.map(|c| c.into() * 2);
+        if min_x != max_x && min_y != max_y {
+            // nothing crosses the interior, so it's either all inside or all outside
+            return self.locate_doubled([(min_x + max_x) / 2, (min_y + max_y) / 2])
+                != PointLocation::Outside;
+        }
+
+        // a flat box is a line segment, which can only enter or leave the polygon at a vertex
+        // (or at its ends), so check each piece between those
+        let axis = if min_x != max_x { 0 } else { 1 };
+        let (min, max) = ([min_x, min_y], [max_x, max_y]);
+        let mut stops = vec![min[axis], max[axis]];
+        for ([x, y], _) in self.doubled_edges() {
+            let vertex = [x, y];
+            if vertex[1 - axis] == min[1 - axis]
+                && min[axis] < vertex[axis]
+                && vertex[axis] < max[axis]
+            {
+                stops.push(vertex[axis]);
+            }
+        }
+        stops.sort_unstable();
+        let mut point = min;
+        stops.windows(2).all(|w| {
+            point[axis] = (w[0] + w[1]) / 2;
+            self.locate_doubled(point) != PointLocation::Outside
+        }) && self.locate_doubled(max) != PointLocation::Outside
+    }
+}
+
+impl<T: Coordinate + Into<i128> + RangeInt> RectilinearPolygon<T> {
+    /// Summed-area table of the points inside the polygon or on its boundary, over a grid
+    /// compressed around its vertices. Its `is_filled` agrees with `contains_box` and always
+    /// takes logarithmic time, but building it costs time and space quadratic in the vertices.
+    pub fn prefix_sum(&self) -> PrefixSum2D<T> {
+        let compression = GridCompression::from_points(&self.vertices);
+        let [num_x, num_y] = compression.axes.each_ref().map(|axis| axis.num_cells());
+        // edges only run along cells of vertex coordinates, so every cell is either on the
+        // boundary, or entirely inside or outside
+        let mut boundary = vec![false; num_x * num_y];
+        for i in 0..self.vertices.len() {
+            let cell1 = compression.point(self.vertices[i]).unwrap();
+            let cell2 = compression
+                .point(self.vertices[(i + 1) % self.vertices.len()])
+                .unwrap();
+            for x in cell1[0].min(cell2[0])..=cell1[0].max(cell2[0]) {
+                for y in cell1[1].min(cell2[1])..=cell1[1].max(cell2[1]) {
+                    boundary[x * num_y + y] = true;
+                }
+            }
+        }
+
+        // the outside is connected, so it's everything reachable from the border of the grid
+        let mut outside = vec![false; num_x * num_y];
+        let mut stack: Vec<[usize; 2]> = (0..num_x)
+            .flat_map(|x| [[x, 0], [x, num_y - 1]])
+            .chain((0..num_y).flat_map(|y| [[0, y], [num_x - 1, y]]))
+            .collect();
+        while let Some([x, y]) = stack.pop() {
+            let cell = x * num_y + y;
+            if boundary[cell] || outside[cell] {
+                continue;
+            }
+            outside[cell] = true;
+            if x > 0 {
+                stack.push([x - 1, y]);
+            }
+            if x + 1 < num_x {
+                stack.push([x + 1, y]);
+            }
+            if y > 0 {
+                stack.push([x, y - 1]);
+            }
+            if y + 1 < num_y {
+                stack.push([x, y + 1]);
+            }
+        }
+        PrefixSum2D::new(compression, |[x, y]| !outside[x * num_y + y])
+    }
+}
+
+/// An integer type that can be stored in a `RangeSet`.
+pub trait RangeInt: Copy + Ord + std::fmt::Debug {
+    const MIN: Self;
+    const MAX: Self;
+
+    fn checked_succ(self) -> Option<Self>;
+    fn checked_pred(self) -> Option<Self>;
+
+    /// Number of integers in `start..=end`, which must not be empty.
+    fn range_len(start: Self, end: Self) -> u128;
+}
+
+macro_rules! impl_range_int {
+    ($($t:ty),*) => {
+        $(
+            impl RangeInt for $t {
+                const MIN: Self = <$t>::MIN;
+                const MAX: Self = <$t>::MAX;
+
+                fn checked_succ(self) -> Option<Self> {
+                    self.checked_add(1)
+                }
+
+                fn checked_pred(self) -> Option<Self> {
+                    self.checked_sub(1)
+                }
+
+                fn range_len(start: Self, end: Self) -> u128 {
+                    end.abs_diff(start) as u128 + 1
+                }
+            }
+        )*
+    };
+}
+
+impl_range_int!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+/// A set of integers, stored as sorted, disjoint ranges. Ranges that overlap or touch are
+/// merged, so every set has exactly one representation.
+///
+/// Ranges are kept inclusive internally, so a set can hold `T::MAX` without overflowing. Any
+/// kind of range can be passed in, e.g. `3..7`, `3..=6` or `3..`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RangeSet<T> {
+    ranges: Vec<(T, T)>,
+}
+
+impl<T: RangeInt> Default for RangeSet<T> {
+    fn default() -> Self {
+        RangeSet::new()
+    }
+}
+
+impl<T: RangeInt, R: RangeBounds<T>> FromIterator<R> for RangeSet<T> {
+    fn from_iter<I: IntoIterator<Item = R>>(iter: I) -> Self {
+        let mut set = RangeSet::new();
+        for range in iter {
+            set.insert(range);
+        }
+        set
+    }
+}
+
+impl<T: RangeInt> RangeSet<T> {
+    pub fn new() -> RangeSet<T> {
+        RangeSet { ranges: Vec::new() }
+    }
+
+    // start and end of a range as an inclusive pair, `None` if the range is empty
+    fn inclusive_bounds(range: impl RangeBounds<T>) -> Option<(T, T)> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.checked_succ()?,
+            Bound::Unbounded => T::MIN,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end,
+            Bound::Excluded(&end) => end.checked_pred()?,
+            Bound::Unbounded => T::MAX,
+        };
+        (start <= end).then_some((start, end))
+    }
+
+    pub fn insert(&mut self, range: impl RangeBounds<T>) {
+        let Some((start, end)) = Self::inclusive_bounds(range) else {
+            return;
+        };
+        // ranges that end right before `start` or start right after `end` are merged too
+        let first = self
+            .ranges
+            .partition_point(|&(_, e)| e.checked_succ().is_some_and(|e| e < start));
+        let last = self
+            .ranges
+            .partition_point(|&(s, _)| end.checked_succ().is_none_or(|end| s <= end));
+        let merged = if first < last {
+            (
+                start.min(self.ranges[first].0),
+                end.max(self.ranges[last - 1].1),
+            )
+        } else {
+            (start, end)
+        };
+        self.ranges.splice(first..last, [merged]);
+    }
+
+    pub fn remove(&mut self, range: impl RangeBounds<T>) {
+        let Some((start, end)) = Self::inclusive_bounds(range) else {
+            return;
+        };
+        let first = self.ranges.partition_point(|&(_, e)| e < start);
+        let last = self.ranges.partition_point(|&(s, _)| s <= end);
+        if first == last {
+            return;
+        }
+        let mut remaining: SmallVec<[(T, T); 2]> = SmallVec::new();
+        let (first_start, _) = self.ranges[first];
+        if first_start < start {
+            // `start` is above some value, so it has a predecessor
+            remaining.push((first_start, start.checked_pred().unwrap()));
+        }
+        let (_, last_end) = self.ranges[last - 1];
+        if last_end > end {
+            remaining.push((end.checked_succ().unwrap(), last_end));
+        }
+        self.ranges.splice(first..last, remaining);
+    }
+
+    pub fn contains(&self, value: T) -> bool {
+        let idx = self.ranges.partition_point(|&(_, e)| e < value);
+        self.ranges.get(idx).is_some_and(|&(s, _)| s <= value)
+    }
+
+    /// Number of integers in the set.
+    pub fn len(&self) -> u128 {
+        self.ranges
+            .iter()
+            .map(|&(start, end)| T::range_len(start, end))
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Number of disjoint ranges the set consists of.
+    pub fn num_ranges(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// The disjoint ranges of the set, in increasing order.
+    pub fn iter(&self) -> impl Iterator<Item = RangeInclusive<T>> + '_ {
+        self.ranges.iter().map(|&(start, end)| start..=end)
+    }
+
+    pub fn union(&self, other: &RangeSet<T>) -> RangeSet<T> {
+        let mut union = self.clone();
+        for &(start, end) in &other.ranges {
+            union.insert(start..=end);
+        }
+        union
+    }
+
+    pub fn intersection(&self, other: &RangeSet<T>) -> RangeSet<T> {
+        let mut ranges = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while let (Some(&(s1, e1)), Some(&(s2, e2))) = (self.ranges.get(i), other.ranges.get(j)) {
+            let (start, end) = (s1.max(s2), e1.min(e2));
+            if start <= end {
+                ranges.push((start, end));
+            }
+            // whichever range ends first can't intersect anything else
+            if e1 < e2 {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        RangeSet { ranges }
+    }
+
+    pub fn difference(&self, other: &RangeSet<T>) -> RangeSet<T> {
+        let mut difference = self.clone();
+        for &(start, end) in &other.ranges {
+            difference.remove(start..=end);
+        }
+        difference
+    }
+
+    /// Integers within `bounds` that are not in the set.
+    pub fn complement(&self, bounds: impl RangeBounds<T>) -> RangeSet<T> {
+        RangeSet::from_iter([bounds]).difference(self)
+    }
+}
+
+/// Splits the integers between the smallest and largest of a set of values into cells: one
+/// cell for each value, and one for each gap between consecutive values that isn't empty.
+/// Within a cell, anything that only depends on where the values are is the same everywhere.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CoordinateCompression<T> {
+    // inclusive start and end of every cell, in increasing order
+    cells: Vec<(T, T)>,
+}
+
+impl<T: RangeInt> CoordinateCompression<T> {
+    pub fn new(values: impl IntoIterator<Item = T>) -> CoordinateCompression<T> {
+        let mut values: Vec<T> = values.into_iter().collect();
+        values.sort_unstable();
+        values.dedup();
+        let mut cells = Vec::with_capacity(values.len() * 2);
+        for (i, &value) in values.iter().enumerate() {
+            cells.push((value, value));
+            if let Some(&next) = values.get(i + 1) {
+                // neither can overflow, since `value < next`
+                let (gap_start, gap_end) =
+                    (value.checked_succ().unwrap(), next.checked_pred().unwrap());
+                if gap_start <= gap_end {
+                    cells.push((gap_start, gap_end));
+                }
+            }
+        }
+        CoordinateCompression { cells }
+    }
+
+    pub fn num_cells(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn cell(&self, idx: usize) -> RangeInclusive<T> {
+        let (start, end) = self.cells[idx];
+        start..=end
+    }
+
+    /// Number of integers in a cell.
+    pub fn cell_len(&self, idx: usize) -> u128 {
+        let (start, end) = self.cells[idx];
+        T::range_len(start, end)
+    }
+
+    /// Index of the cell containing `value`, `None` if it's outside all cells.
+    pub fn index(&self, value: T) -> Option<usize> {
+        let idx = self.cells.partition_point(|&(_, end)| end < value);
+        self.cells
+            .get(idx)
+            .and_then(|&(start, _)| (start <= value).then_some(idx))
+    }
+
+    // number of cells that are entirely `<= value`, and how many integers of the next cell are
+    fn split(&self, value: T) -> (usize, u128) {
+        let idx = self.cells.partition_point(|&(_, end)| end <= value);
+        match self.cells.get(idx) {
+            Some(&(start, _)) if start <= value => (idx, T::range_len(start, value)),
+            _ => (idx, 0),
+        }
+    }
+}
+
+/// Coordinate compression of both axes of a 2D grid.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GridCompression<T> {
+    pub axes: [CoordinateCompression<T>; 2],
+}
+
+impl<T: RangeInt> GridCompression<T> {
+    pub fn from_points(points: &[[T; 2]]) -> GridCompression<T> {
+        GridCompression {
+            axes: [0, 1].map(|axis| CoordinateCompression::new(points.iter().map(|p| p[axis]))),
+        }
+    }
+
+    /// Compression in which every corner of the given boxes starts or ends a cell.
+    pub fn from_boxes(boxes: &[AaBox<T, 2>]) -> GridCompression<T> {
+        let corners: Vec<[T; 2]> = boxes.iter().flat_map(|b| [b.min, b.max]).collect();
+        GridCompression::from_points(&corners)
+    }
+
+    /// The cell containing `point`, `None` if it's outside the grid.
+    pub fn point(&self, point: [T; 2]) -> Option<[usize; 2]> {
+        Some([self.axes[0].index(point[0])?, self.axes[1].index(point[1])?])
+    }
+
+    /// The cells touched by `area`, `None` if any of its corners is outside the grid.
+    pub fn area(&self, area: &AaBox<T, 2>) -> Option<AaBox<usize, 2>> {
+        Some(AaBox {
+            min: self.point(area.min)?,
+            max: self.point(area.max)?,
+        })
+    }
+}
+
+/// Summed-area table over the cells of a `GridCompression`, counting the integer points in
+/// filled cells. Everything outside the grid counts as not filled.
+#[derive(Debug, Clone)]
+pub struct PrefixSum2D<T> {
+    compression: GridCompression<T>,
+    // `sums[i * (num_y + 1) + j]` is the number of filled points in cells `< [i, j]`
+    sums: Vec<u128>,
+}
+
+impl<T: RangeInt> PrefixSum2D<T> {
+    /// Build the table, calling `filled` once for every cell.
+    pub fn new(
+        compression: GridCompression<T>,
+        filled: impl Fn([usize; 2]) -> bool,
+    ) -> PrefixSum2D<T> {
+        let [num_x, num_y] = compression.axes.each_ref().map(|axis| axis.num_cells());
+        let mut sums = vec![0; (num_x + 1) * (num_y + 1)];
+        for i in 0..num_x {
+            for j in 0..num_y {
+                let cell = if filled([i, j]) {
+                    compression.axes[0].cell_len(i) * compression.axes[1].cell_len(j)
+                } else {
+                    0
+                };
+                sums[(i + 1) * (num_y + 1) + j + 1] =
+                    cell + sums[i * (num_y + 1) + j + 1] + sums[(i + 1) * (num_y + 1) + j]
+                        - sums[i * (num_y + 1) + j];
+            }
+        }
+        PrefixSum2D { compression, sums }
+    }
+
+    pub fn compression(&self) -> &GridCompression<T> {
+        &self.compression
+    }
+
+    fn sum(&self, i: usize, j: usize) -> u128 {
+        self.sums[i * (self.compression.axes[1].num_cells() + 1) + j]
+    }
+
+    // filled points `<= [x, y]`, where `None` is below every coordinate
+    fn filled_up_to(&self, x: Option<T>, y: Option<T>) -> u128 {
+        let (Some(x), Some(y)) = (x, y) else {
+            return 0;
+        };
+        let (i, part_x) = self.compression.axes[0].split(x);
+        let (j, part_y) = self.compression.axes[1].split(y);
+        let mut count = self.sum(i, j);
+        // `x` and `y` can cut through a cell, which is filled either everywhere or nowhere
+        if part_x > 0 {
+            let column = self.sum(i + 1, j) - self.sum(i, j);
+            count += column / self.compression.axes[0].cell_len(i) * part_x;
+        }
+        if part_y > 0 {
+            let row = self.sum(i, j + 1) - self.sum(i, j);
+            count += row / self.compression.axes[1].cell_len(j) * part_y;
+        }
+        if part_x > 0 && part_y > 0 {
+            let cell =
+                self.sum(i + 1, j + 1) + self.sum(i, j) - self.sum(i + 1, j) - self.sum(i, j + 1);
+            if cell > 0 {
+                count += part_x * part_y;
+            }
+        }
+        count
+    }
+
+    /// Number of filled integer points in `area`, which can have any coordinates.
+    pub fn count_filled(&self, area: &AaBox<T, 2>) -> u128 {
+        let [min_x, min_y] = area.min.map(|c| c.checked_pred());
+        let [max_x, max_y] = area.max.map(Some);
+        self.filled_up_to(max_x, max_y) + self.filled_up_to(min_x, min_y)
+            - self.filled_up_to(min_x, max_y)
+            - self.filled_up_to(max_x, min_y)
+    }
+
+    /// Whether every integer point in `area` is filled.
+    pub fn is_filled(&self, area: &AaBox<T, 2>) -> bool {
+        let points =
+            T::range_len(area.min[0], area.max[0]) * T::range_len(area.min[1], area.max[1]);
+        self.count_filled(area) == points
+    }
+}
+
+pub trait ZeroExt {
+    fn zero() -> Self;
+    fn is_zero(&self) -> bool;
+}
+
+pub trait OneExt {
+    fn one() -> Self;
+}
+
+pub trait AbsExt {
+    fn abs(self) -> Self;
+}
+
+/// Arithmetic that returns `None` on overflow (or division by zero) instead of wrapping or
+/// panicking.
+pub trait CheckedExt: Sized {
+    fn checked_add(&self, rhs: &Self) -> Option<Self>;
+    fn checked_sub(&self, rhs: &Self) -> Option<Self>;
+    fn checked_mul(&self, rhs: &Self) -> Option<Self>;
+    fn checked_div(&self, rhs: &Self) -> Option<Self>;
+}
+
+pub trait NumExt:
+    Mul<Output = Self>
+    + MulAssign
+    + Div<Output = Self>
+    + DivAssign
+    + Add<Output = Self>
+    + AddAssign
+    + Sub<Output = Self>
+    + SubAssign
+    + ZeroExt
+    + OneExt
+    + AbsExt
+    + CheckedExt
+    + PartialOrd
+    + Clone
+{
+}
+
+/// A `NumExt` whose division is exact, so Gaussian elimination stays correct: `Rational`, `f64`
+/// (up to rounding) and `ModInt`. Integer division truncates, so integer matrices only get the
+/// ring operations and the division-free `Mat::bareiss` family.
+pub trait FieldExt: NumExt {}
+
+macro_rules! impl_num_int {
+    ($($t:ty),*) => {
+        $(
+            impl ZeroExt for $t {
+                fn zero() -> Self {
+                    0
+                }
+
+                fn is_zero(&self) -> bool {
+                    *self == 0
+                }
+            }
+
+            impl OneExt for $t {
+                fn one() -> Self {
+                    1
+                }
+            }
+
+            impl AbsExt for $t {
+                fn abs(self) -> Self {
+                    <$t>::abs(self)
+                }
+            }
+
+            impl CheckedExt for $t {
+                fn checked_add(&self, rhs: &Self) -> Option<Self> {
+                    <$t>::checked_add(*self, *rhs)
+                }
+
+                fn checked_sub(&self, rhs: &Self) -> Option<Self> {
+                    <$t>::checked_sub(*self, *rhs)
+                }
+
+                fn checked_mul(&self, rhs: &Self) -> Option<Self> {
+                    <$t>::checked_mul(*self, *rhs)
+                }
+
+                fn checked_div(&self, rhs: &Self) -> Option<Self> {
+                    <$t>::checked_div(*self, *rhs)
+                }
+            }
+
+            impl NumExt for $t {}
+        )*
+    };
+}
+
+impl_num_int!(i8, i16, i32, i64, i128, isize);
+
+/// Magnitude below which an `f64` counts as zero, so rounding noise left over from
+/// elimination isn't picked as a pivot.
+pub const F64_EPSILON: f64 = 1e-9;
+
+impl ZeroExt for f64 {
+    fn zero() -> Self {
+        0.0
+    }
+
+    fn is_zero(&self) -> bool {
+        f64::abs(*self) < F64_EPSILON
+    }
+}
+
+impl OneExt for f64 {
+    fn one() -> Self {
+        1.0
+    }
+}
+
+impl AbsExt for f64 {
+    fn abs(self) -> Self {
+        f64::abs(self)
+    }
+}
+
+/// Fails when the result isn't finite, i.e. on overflow or division by zero.
+impl CheckedExt for f64 {
+    fn checked_add(&self, rhs: &Self) -> Option<Self> {
+        Some(self + rhs).filter(|v| v.is_finite())
+    }
+
+    fn checked_sub(&self, rhs: &Self) -> Option<Self> {
+        Some(self - rhs).filter(|v| v.is_finite())
+    }
+
+    fn checked_mul(&self, rhs: &Self) -> Option<Self> {
+        Some(self * rhs).filter(|v| v.is_finite())
+    }
+
+    fn checked_div(&self, rhs: &Self) -> Option<Self> {
+        Some(self / rhs).filter(|v| v.is_finite())
+    }
+}
+
+impl NumExt for f64 {}
+
+impl FieldExt for f64 {}
+
+/// Error returned when an exact computation does not fit its number type.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Overflow;
+
+impl Display for Overflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("arithmetic overflow")
+    }
+}
+
+impl std::error::Error for Overflow {}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Mat<T> {
+    data: Box<[T]>,
+    cols: usize,
+    rows: usize,
+}
+
+impl<T: CheckedExt> Mat<T> {
+    fn sub_n_rows_from_row(
+        &mut self,
+        row: usize,
+        n: &T,
+        row_to_sub: usize,
+    ) -> Result<(), Overflow> {
+        let row_start = row * self.cols;
+        let row_to_sub_start = row_to_sub * self.cols;
+        for col in 0..self.cols {
+            let sub = n
+                .checked_mul(&self.data[row_to_sub_start + col])
+                .ok_or(Overflow)?;
+            let item = &mut self.data[row_start + col];
+            *item = item.checked_sub(&sub).ok_or(Overflow)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T> Mat<T>
+where
+    T: FieldExt,
+{
+    fn div_row_by(&mut self, row: usize, value: &T) -> Result<(), Overflow> {
+        for item in &mut self.data[row * self.cols..(row + 1) * self.cols] {
+            *item = item.checked_div(value).ok_or(Overflow)?;
+        }
+        Ok(())
+    }
+
+    fn checked_abs(value: &T) -> Result<T, Overflow> {
+        if *value < T::zero() {
+            T::zero().checked_sub(value).ok_or(Overflow)
+        } else {
+            Ok(value.clone())
+        }
+    }
+
+    fn highest_row_below(
+        &self,
+        start_row: usize,
+        column: usize,
+    ) -> Result<Option<usize>, Overflow> {
+        let mut cur_val: Option<T> = None;
+        let mut best_row = None;
+        for row in start_row..self.rows {
+            let new_val = &self[(row, column)];
+            if new_val.is_zero() {
+                continue;
+            }
+            let new_abs = Self::checked_abs(new_val)?;
+            if cur_val.as_ref().is_none_or(|v| new_abs > *v) {
+                cur_val = Some(new_abs);
+                best_row = Some(row);
+            }
+        }
+        Ok(best_row)
+    }
+
+    /// Brings the matrix into reduced row echelon form. If `augmented_mat` is set, the last
+    /// column is not used as a pivot column.
+    ///
+    /// Returns the pivot column of each non-zero row. Fails if an intermediate value overflows
+    /// `T`, leaving the matrix partially reduced.
+    pub fn to_rref(&mut self, augmented_mat: bool) -> Result<Vec<usize>, Overflow> {
+        let mut pivot_cols = Vec::new();
+        let mut non_zero_col = 0;
+        let max_cols = if augmented_mat {
+            self.cols.saturating_sub(1)
+        } else {
+            self.cols
+        };
+        // bring each row to ref
+        for i in 0..self.rows {
+            while non_zero_col < max_cols {
+                // if we found some non-zero row
+                if let Some(row) = self.highest_row_below(i, non_zero_col)? {
+                    if row != i {
+                        self.swap_rows(i, row);
+                    }
+                    // normalize the row
+                    let val = self[(i, non_zero_col)].clone();
+                    self.div_row_by(i, &val)?;
+
+                    // eliminate all other rows
+                    for other_row in 0..self.rows {
+                        if other_row != i {
+                            let val = self[(other_row, non_zero_col)].clone();
+                            if !val.is_zero() {
+                                self.sub_n_rows_from_row(other_row, &val, i)?;
+                            }
+                        }
+                    }
+
+                    pivot_cols.push(non_zero_col);
+                    non_zero_col += 1;
+                    break;
+                }
+                non_zero_col += 1;
+            }
+        }
+        Ok(pivot_cols)
+    }
+
+    /// Solves the system whose right-hand side is the last column, reducing the matrix to
+    /// reduced row echelon form in place.
+    pub fn solve_augmented(&mut self) -> Result<AugmentedSolution<T>, Overflow> {
+        if self.cols == 0 {
+            panic!("augmented matrix needs a right-hand side column");
+        }
+        let pivot_cols = self.to_rref(true)?;
+        let vars = self.cols - 1;
+        let mut is_pivot = vec![false; vars];
+        for &col in &pivot_cols {
+            is_pivot[col] = true;
+        }
+        let free_cols: Vec<usize> = (0..vars).filter(|&col| !is_pivot[col]).collect();
+
+        // rows without a pivot have zero coefficients, so they must have a zero right-hand side
+        let consistent = (pivot_cols.len()..self.rows).all(|row| self[(row, vars)].is_zero());
+        let solutions = if !consistent {
+            Solutions::Inconsistent
+        } else {
+            let mut particular = vec![T::zero(); vars];
+            for (row, &col) in pivot_cols.iter().enumerate() {
+                particular[col] = self[(row, vars)].clone();
+            }
+            if free_cols.is_empty() {
+                Solutions::Unique(particular)
+            } else {
+                // one basis vector per free column: set it to one and solve for the pivots
+                let mut null_space = Vec::with_capacity(free_cols.len());
+                for &free_col in &free_cols {
+                    let mut basis = vec![T::zero(); vars];
+                    basis[free_col] = T::one();
+                    for (row, &col) in pivot_cols.iter().enumerate() {
+                        basis[col] = T::zero()
+                            .checked_sub(&self[(row, free_col)])
+                            .ok_or(Overflow)?;
+                    }
+                    null_space.push(basis);
+                }
+                Solutions::Affine {
+                    particular,
+                    null_space,
+                }
+            }
+        };
+        Ok(AugmentedSolution {
+            pivot_cols,
+            free_cols,
+            solutions,
+        })
+    }
+}
+
+/// The solutions of a linear system `Ax = b`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Solutions<T> {
+    Inconsistent,
+    Unique(Vec<T>),
+    /// Every `particular + sum(c_i * null_space[i])`, with one basis vector per free column.
+    Affine {
+        particular: Vec<T>,
+        null_space: Vec<Vec<T>>,
+    },
+}
+
+/// Result of `Mat::solve_augmented`. Row `i` of the reduced matrix has its pivot in
+/// `pivot_cols[i]`; the remaining variables are `free_cols`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AugmentedSolution<T> {
+    pub pivot_cols: Vec<usize>,
+    pub free_cols: Vec<usize>,
+    pub solutions: Solutions<T>,
+}
+
+/// An optimal solution found by `Mat::minimize_integer`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct IntegerSolution<T = i64> {
+    pub x: Vec<Rational<T>>,
+    pub objective: Rational<T>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IntegerProgramError {
+    /// The linear relaxation is unbounded, so a feasible integer program has no minimum.
+    Unbounded,
+    Overflow,
+}
+
+impl From<Overflow> for IntegerProgramError {
+    fn from(_: Overflow) -> Self {
+        IntegerProgramError::Overflow
+    }
+}
+
+impl Display for IntegerProgramError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            IntegerProgramError::Unbounded => "integer program is unbounded",
+            IntegerProgramError::Overflow => "arithmetic overflow",
+        })
+    }
+}
+
+impl std::error::Error for IntegerProgramError {}
+
+/// Integer bounds of a variable, `None` being an unbounded maximum.
+type IntegerDomain<T> = (Rational<T>, Option<Rational<T>>);
+
+/// Depth-first branch and bound over the free variables of a reduced system.
+struct IntegerSearch<T> {
+    rows: Vec<(Vec<Rational<T>>, Rational<T>)>,
+    particular: Vec<Rational<T>>,
+    null_space: Vec<Vec<Rational<T>>>,
+    free_cols: Vec<usize>,
+    objective: Vec<Rational<T>>,
+    // the objective at `particular`, and its change per unit of each free variable
+    base_cost: Rational<T>,
+    free_costs: Vec<Rational<T>>,
+    // integer coefficients give integer objective values, so bounds can be rounded up
+    integral_objective: bool,
+    // the optimum of the linear relaxation, whose objective is a lower bound for every node
+    relaxation: LinearSolution<T>,
+    best: Option<IntegerSolution<T>>,
+}
+
+impl<T: RationalInt> IntegerSearch<T> {
+    const MAX_PROPAGATION_PASSES: usize = 64;
+
+    /// Tightens the domains with every row's bounds on each variable, returns `false` if some
+    /// domain becomes empty. Once there is a solution, `objective · x <= best` is a row too.
+    fn propagate(&self, domains: &mut [IntegerDomain<T>]) -> Result<bool, Overflow> {
+        let incumbent = match &self.best {
+            Some(best) if self.integral_objective => Some(
+                best.objective
+                    .checked_sub(&Rational::one())
+                    .ok_or(Overflow)?,
+            ),
+            Some(best) => Some(best.objective.clone()),
+            None => None,
+        };
+        let rows = self
+            .rows
+            .iter()
+            .map(|(coeffs, rhs)| (coeffs, rhs, true))
+            .chain(incumbent.iter().map(|rhs| (&self.objective, rhs, false)));
+        for _ in 0..Self::MAX_PROPAGATION_PASSES {
+            let mut changed = false;
+            for (coeffs, rhs, equality) in rows.clone() {
+                // each term's range, `None` being infinite
+                let terms = coeffs
+                    .iter()
+                    .zip(domains.iter())
+                    .map(|(a, (lo, hi))| {
+                        let at_lo = a.checked_mul(lo).ok_or(Overflow)?;
+                        let at_hi = hi
+                            .as_ref()
+                            .map(|hi| a.checked_mul(hi).ok_or(Overflow))
+                            .transpose()?;
+                        Ok(if *a < Rational::zero() {
+                            (at_hi, Some(at_lo))
+                        } else {
+                            (Some(at_lo), at_hi)
+                        })
+                    })
+                    .collect::<Result<Vec<_>, Overflow>>()?;
+                let (mut min_sum, mut max_sum) = (Rational::zero(), Rational::zero());
+                let (mut min_infinite, mut max_infinite) = (0, 0);
+                for (min, max) in &terms {
+                    match min {
+                        Some(min) => min_sum = min_sum.checked_add(min).ok_or(Overflow)?,
+                        None => min_infinite += 1,
+                    }
+                    match max {
+                        Some(max) => max_sum = max_sum.checked_add(max).ok_or(Overflow)?,
+                        None => max_infinite += 1,
+                    }
+                }
+
+                for (col, a) in coeffs.iter().enumerate() {
+                    if a.is_zero() {
+                        continue;
+                    }
+                    let (min, max) = &terms[col];
+                    // the range of all other terms, when finite
+                    let rest_min = match min {
+                        Some(min) if min_infinite == 0 => {
+                            Some(min_sum.checked_sub(min).ok_or(Overflow)?)
+                        }
+                        None if min_infinite == 1 => Some(min_sum.clone()),
+                        _ => None,
+                    };
+                    // an inequality `a · x <= rhs` only bounds by the other terms' minimum
+                    let rest_max = match max {
+                        _ if !equality => None,
+                        Some(max) if max_infinite == 0 => {
+                            Some(max_sum.checked_sub(max).ok_or(Overflow)?)
+                        }
+                        None if max_infinite == 1 => Some(max_sum.clone()),
+                        _ => None,
+                    };
+                    // a * x = rhs - rest, so x lies between (rhs - rest_max) / a and
+                    // (rhs - rest_min) / a
+                    let bound =
+                        |rest: Option<Rational<T>>| -> Result<Option<Rational<T>>, Overflow> {
+                            rest.map(|rest| {
+                                rhs.checked_sub(&rest)
+                                    .and_then(|v| v.checked_div(a))
+                                    .ok_or(Overflow)
+                            })
+                            .transpose()
+                        };
+                    let (upper, lower) = if *a > Rational::zero() {
+                        (bound(rest_min)?, bound(rest_max)?)
+                    } else {
+                        (bound(rest_max)?, bound(rest_min)?)
+                    };
+
+                    let (lo, hi) = &mut domains[col];
+                    if let Some(lower) = lower.map(|v| v.ceil())
+                        && lower > *lo
+                    {
+                        *lo = lower;
+                        changed = true;
+                    }
+                    if let Some(upper) = upper.map(|v| v.floor())
+                        && hi.as_ref().is_none_or(|hi| upper < *hi)
+                    {
+                        *hi = Some(upper);
+                        changed = true;
+                    }
+                    if hi.as_ref().is_some_and(|hi| hi < lo) {
+                        return Ok(false);
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        Ok(true)
+    }
+
+    /// A lower bound on the objective over the domains, all free domains being bounded.
+    fn lower_bound(&self, domains: &[IntegerDomain<T>]) -> Result<Rational<T>, Overflow> {
+        let mut bound = self.base_cost.clone();
+        for (cost, &col) in self.free_costs.iter().zip(&self.free_cols) {
+            let (lo, hi) = &domains[col];
+            let hi = hi.as_ref().expect("free variables are bounded");
+            let cheapest = if *cost < Rational::zero() { hi } else { lo };
+            bound = bound
+                .checked_add(&cost.checked_mul(cheapest).ok_or(Overflow)?)
+                .ok_or(Overflow)?;
+        }
+        Ok(bound)
+    }
+
+    fn search(&mut self, mut domains: Vec<IntegerDomain<T>>) -> Result<(), Overflow> {
+        if !self.propagate(&mut domains)? {
+            return Ok(());
+        }
+        let bound = self.lower_bound(&domains)?;
+        let mut rounded = if self.relaxation.objective > bound {
+            self.relaxation.objective.clone()
+        } else {
+            bound.clone()
+        };
+        if self.integral_objective {
+            rounded = rounded.ceil();
+        }
+        if self
+            .best
+            .as_ref()
+            .is_some_and(|best| rounded >= best.objective)
+        {
+            return Ok(());
+        }
+
+        // branch on the unfixed free variable with the fewest values
+        let mut branch = None;
+        for (i, &col) in self.free_cols.iter().enumerate() {
+            let (lo, hi) = &domains[col];
+            let width = hi.as_ref().unwrap().checked_sub(lo).ok_or(Overflow)?;
+            if !width.is_zero() && branch.as_ref().is_none_or(|(_, best)| width < *best) {
+                branch = Some((i, width));
+            }
+        }
+
+        let Some((i, _)) = branch else {
+            // all free variables are fixed, which determines the pivot variables
+            let mut x = self.particular.clone();
+            for (basis, &col) in self.null_space.iter().zip(&self.free_cols) {
+                let value = &domains[col].0;
+                for (x, b) in x.iter_mut().zip(basis) {
+                    *x = x
+                        .checked_add(&b.checked_mul(value).ok_or(Overflow)?)
+                        .ok_or(Overflow)?;
+                }
+            }
+            if x.iter()
+                .all(|v| v.to_int().is_some() && *v >= Rational::zero())
+            {
+                // `bound` is exact once every free variable is fixed
+                self.best = Some(IntegerSolution {
+                    x,
+                    objective: bound,
+                });
+            }
+            return Ok(());
+        };
+
+        // split the domain in halves, trying the half nearer the relaxation's optimum first to
+        // find good solutions early
+        let col = self.free_cols[i];
+        let (lo, hi) = domains[col].clone();
+        let hi = hi.unwrap();
+        let mid = lo
+            .checked_add(&hi)
+            .and_then(|sum| sum.checked_div(&Rational::from_int(2)))
+            .ok_or(Overflow)?
+            .floor();
+        let above_mid = mid.checked_add(&Rational::from_int(1)).ok_or(Overflow)?;
+        let near_lower = self.relaxation.x[col] <= mid;
+        let mut lower = domains.clone();
+        lower[col] = (lo, Some(mid));
+        let mut upper = domains;
+        upper[col] = (above_mid, Some(hi));
+        let halves = if near_lower {
+            [lower, upper]
+        } else {
+            [upper, lower]
+        };
+        for half in halves {
+            self.search(half)?;
+        }
+        Ok(())
+    }
+}
+
+/// An optimal vertex found by `Mat::minimize_linear`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LinearSolution<T = i64> {
+    pub x: Vec<Rational<T>>,
+    pub objective: Rational<T>,
+    /// One value per constraint, satisfying `duals · A <= objective` with `duals · b` equal to
+    /// the optimal objective.
+    pub duals: Vec<Rational<T>>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum LinearOptimum<T = i64> {
+    Optimal(LinearSolution<T>),
+    Infeasible,
+    Unbounded,
+}
+
+/// A simplex tableau over constraint rows and a last objective row, which holds the reduced
+/// costs and, in the last column, the negated objective value.
+struct SimplexTableau<T> {
+    mat: Mat<Rational<T>>,
+    basis: Vec<usize>,
+}
+
+impl<T: RationalInt> SimplexTableau<T> {
+    fn pivot(&mut self, row: usize, col: usize) -> Result<(), Overflow> {
+        let val = self.mat[(row, col)].clone();
+        self.mat.div_row_by(row, &val)?;
+        for other_row in 0..self.mat.rows {
+            let factor = self.mat[(other_row, col)].clone();
+            if other_row != row && !factor.is_zero() {
+                self.mat.sub_n_rows_from_row(other_row, &factor, row)?;
+            }
+        }
+        self.basis[row] = col;
+        Ok(())
+    }
+
+    /// Pivots with Bland's rule, which can't cycle, letting only columns below `entering_cols`
+    /// enter the basis. Returns `false` if the objective is unbounded.
+    fn optimize(&mut self, entering_cols: usize) -> Result<bool, Overflow> {
+        let objective_row = self.basis.len();
+        let rhs = self.mat.cols - 1;
+        loop {
+            let Some(col) =
+                (0..entering_cols).find(|&col| self.mat[(objective_row, col)] < Rational::zero())
+            else {
+                return Ok(true);
+            };
+            let mut leaving: Option<(usize, Rational<T>)> = None;
+            for row in 0..objective_row {
+                let coeff = &self.mat[(row, col)];
+                if *coeff <= Rational::zero() {
+                    continue;
+                }
+                let ratio = self.mat[(row, rhs)].checked_div(coeff).ok_or(Overflow)?;
+                let better = leaving.as_ref().is_none_or(|(best_row, best)| {
+                    ratio < *best || (ratio == *best && self.basis[row] < self.basis[*best_row])
+                });
+                if better {
+                    leaving = Some((row, ratio));
+                }
+            }
+            let Some((row, _)) = leaving else {
+                return Ok(false);
+            };
+            self.pivot(row, col)?;
+        }
+    }
+}
+
+impl<T: RationalInt> Mat<Rational<T>> {
+    /// Minimises `objective · x` subject to `Ax = b`, `x >= 0` and `x` integer, where the matrix
+    /// is `A` augmented with `b`. Returns `None` if there is no integer solution.
+    ///
+    /// Branch and bound over the free variables of the reduced system, which explores every
+    /// integer point that bound propagation and the objective bounds (including the linear
+    /// relaxation's optimum) can't rule out, so the solution is optimal. Variables the
+    /// constraints leave unbounded are capped near the relaxation's optimum, which keeps an
+    /// optimal solution. Fails with `Unbounded` if the linear relaxation is unbounded.
+    pub fn minimize_integer(
+        &self,
+        objective: &[Rational<T>],
+    ) -> Result<Option<IntegerSolution<T>>, IntegerProgramError> {
+        if objective.len() + 1 != self.cols {
+            panic!("objective needs one coefficient per variable");
+        }
+        let mut reduced = self.clone();
+        let solution = reduced.solve_augmented()?;
+        let (particular, null_space) = match solution.solutions {
+            Solutions::Inconsistent => return Ok(None),
+            Solutions::Unique(particular) => (particular, Vec::new()),
+            Solutions::Affine {
+                particular,
+                null_space,
+            } => (particular, null_space),
+        };
+
+        let dot = |a: &[Rational<T>], b: &[Rational<T>]| -> Result<Rational<T>, Overflow> {
+            let mut sum = Rational::zero();
+            for (a, b) in a.iter().zip(b) {
+                sum = sum
+                    .checked_add(&a.checked_mul(b).ok_or(Overflow)?)
+                    .ok_or(Overflow)?;
+            }
+            Ok(sum)
+        };
+        let base_cost = dot(objective, &particular)?;
+        let free_costs = null_space
+            .iter()
+            .map(|basis| dot(objective, basis))
+            .collect::<Result<_, _>>()?;
+
+        // the original rows bound variables through their signs, the reduced rows tie the
+        // pivot variables to the free ones
+        let rows = self
+            .iter_rows()
+            .chain(reduced.iter_rows().take(solution.pivot_cols.len()))
+            .map(|row| {
+                let (rhs, coeffs) = row.split_last().unwrap();
+                (coeffs.to_vec(), rhs.clone())
+            })
+            .collect();
+
+        let relaxation = match self.minimize_linear(objective)? {
+            LinearOptimum::Optimal(relaxation) => relaxation,
+            LinearOptimum::Infeasible => return Ok(None),
+            LinearOptimum::Unbounded => return Err(IntegerProgramError::Unbounded),
+        };
+        let mut search = IntegerSearch {
+            rows,
+            particular,
+            null_space,
+            free_cols: solution.free_cols,
+            objective: objective.to_vec(),
+            base_cost,
+            free_costs,
+            integral_objective: objective.iter().all(|c| c.to_int().is_some()),
+            relaxation,
+            best: None,
+        };
+        let mut domains = vec![(Rational::zero(), None); objective.len()];
+        if !search.propagate(&mut domains)? {
+            return Ok(None);
+        }
+        if domains.iter().any(|(_, hi)| hi.is_none()) {
+            // some integer optimum lies within n * (largest subdeterminant of A) of the
+            // relaxation's optimum in every coordinate (Cook, Gerards, Schrijver and Tardos)
+            let reach = Rational::from_int(objective.len() as i32)
+                .checked_mul(&self.subdeterminant_bound()?)
+                .ok_or(Overflow)?;
+            for ((_, hi), x) in domains.iter_mut().zip(&search.relaxation.x) {
+                if hi.is_none() {
+                    *hi = Some(x.checked_add(&reach).ok_or(Overflow)?.floor());
+                }
+            }
+            if !search.propagate(&mut domains)? {
+                return Ok(None);
+            }
+        }
+        search.search(domains)?;
+        Ok(search.best)
+    }
+
+    /// An upper bound on the absolute value of every subdeterminant of `A`, once each row is
+    /// scaled to integers, from Hadamard's inequality with the rows' sums of absolute values.
+    fn subdeterminant_bound(&self) -> Result<Rational<T>, Overflow> {
+        let vars = self.cols - 1;
+        let mut bound = Rational::one();
+        for row in self.iter_rows() {
+            let mut scale = T::from(1);
+            for value in &row[..vars] {
+                let gcd =
+                    checked_gcd(scale.clone(), value.denominator().clone()).ok_or(Overflow)?;
+                scale = scale
+                    .checked_div(&gcd)
+                    .and_then(|scale| scale.checked_mul(value.denominator()))
+                    .ok_or(Overflow)?;
+            }
+            let mut norm = Rational::zero();
+            for value in &row[..vars] {
+                let abs = if *value < Rational::zero() {
+                    value.checked_neg().ok_or(Overflow)?
+                } else {
+                    value.clone()
+                };
+                norm = norm.checked_add(&abs).ok_or(Overflow)?;
+            }
+            norm = norm
+                .checked_mul(&Rational::from_int(scale))
+                .ok_or(Overflow)?;
+            if norm > Rational::one() {
+                bound = bound.checked_mul(&norm).ok_or(Overflow)?;
+            }
+        }
+        Ok(bound)
+    }
+
+    /// Minimises `objective · x` subject to `Ax = b` and `x >= 0`, where the matrix is `A`
+    /// augmented with `b`, with the two-phase simplex method in exact arithmetic.
+    pub fn minimize_linear(&self, objective: &[Rational<T>]) -> Result<LinearOptimum<T>, Overflow> {
+        if objective.len() + 1 != self.cols {
+            panic!("objective needs one coefficient per variable");
+        }
+        let vars = objective.len();
+        let constraints = self.rows;
+        // one artificial variable per constraint, then the right-hand side
+        let rhs = vars + constraints;
+        let mut mat = Mat::with_value(rhs + 1, constraints + 1, Rational::zero());
+        let mut negated = Vec::with_capacity(constraints);
+        for row in 0..constraints {
+            // artificial variables need a non-negative right-hand side
+            let negate = self[(row, vars)] < Rational::zero();
+            for (col, value) in self.row(row).iter().enumerate() {
+                let value = if negate {
+                    value.checked_neg().ok_or(Overflow)?
+                } else {
+                    value.clone()
+                };
+                mat[(row, if col == vars { rhs } else { col })] = value;
+            }
+            mat[(row, vars + row)] = Rational::one();
+            negated.push(negate);
+        }
+
+        // phase one minimises the sum of the artificial variables, starting from them as basis
+        for col in (0..vars).chain([rhs]) {
+            let mut reduced_cost = Rational::zero();
+            for row in 0..constraints {
+                reduced_cost = reduced_cost.checked_sub(&mat[(row, col)]).ok_or(Overflow)?;
+            }
+            mat[(constraints, col)] = reduced_cost;
+        }
+        let mut tableau = SimplexTableau {
+            mat,
+            basis: (vars..rhs).collect(),
+        };
+        tableau.optimize(rhs)?;
+        if !tableau.mat[(constraints, rhs)].is_zero() {
+            return Ok(LinearOptimum::Infeasible);
+        }
+        // artificial variables left in the basis are zero, swap them for real ones where the
+        // row isn't redundant
+        for row in 0..constraints {
+            if tableau.basis[row] >= vars
+                && let Some(col) = (0..vars).find(|&col| !tableau.mat[(row, col)].is_zero())
+            {
+                tableau.pivot(row, col)?;
+            }
+        }
+
+        // phase two prices the real objective against the feasible basis
+        for col in 0..=rhs {
+            tableau.mat[(constraints, col)] = match objective.get(col) {
+                Some(cost) => cost.clone(),
+                None => Rational::zero(),
+            };
+        }
+        for row in 0..constraints {
+            if let Some(cost) = objective.get(tableau.basis[row])
+                && !cost.is_zero()
+            {
+                tableau.mat.sub_n_rows_from_row(constraints, cost, row)?;
+            }
+        }
+        if !tableau.optimize(vars)? {
+            return Ok(LinearOptimum::Unbounded);
+        }
+
+        let mut x = vec![Rational::zero(); vars];
+        for (row, &col) in tableau.basis.iter().enumerate() {
+            if col < vars {
+                x[col] = tableau.mat[(row, rhs)].clone();
+            }
+        }
+        // an artificial column's reduced cost is its negated dual value
+        let duals = (0..constraints)
+            .map(|row| {
+                let reduced_cost = &tableau.mat[(constraints, vars + row)];
+                if negated[row] {
+                    Some(reduced_cost.clone())
+                } else {
+                    reduced_cost.checked_neg()
+                }
+            })
+            .collect::<Option<_>>()
+            .ok_or(Overflow)?;
+        Ok(LinearOptimum::Optimal(LinearSolution {
+            x,
+            objective: tableau.mat[(constraints, rhs)]
+                .checked_neg()
+                .ok_or(Overflow)?,
+            duals,
+        }))
+    }
+}
+
+/// Result of `Mat::hermite_normal_form`: `u * a == h`, with `u` unimodular.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct HermiteForm<T> {
+    pub h: Mat<T>,
+    pub u: Mat<T>,
+}
+
+/// Result of `Mat::smith_normal_form`: `u * a * v == s`, with `u` and `v` unimodular.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SmithForm<T> {
+    pub u: Mat<T>,
+    pub s: Mat<T>,
+    pub v: Mat<T>,
+}
+
+impl<T: RationalInt> Mat<T> {
+    /// Replaces rows `i` and `j` with `a * row_i + b * row_j` and `c * row_i + d * row_j`.
+    fn combine_rows(&mut self, i: usize, j: usize, [a, b, c, d]: &[T; 4]) -> Result<(), Overflow> {
+        for col in 0..self.cols {
+            let (x, y) = (&self[(i, col)], &self[(j, col)]);
+            let new_x = checked_dot(a, x, b, y).ok_or(Overflow)?;
+            let new_y = checked_dot(c, x, d, y).ok_or(Overflow)?;
+            self[(i, col)] = new_x;
+            self[(j, col)] = new_y;
+        }
+        Ok(())
+    }
+
+    /// Column version of `combine_rows`.
+    fn combine_cols(&mut self, i: usize, j: usize, [a, b, c, d]: &[T; 4]) -> Result<(), Overflow> {
+        for row in 0..self.rows {
+            let (x, y) = (&self[(row, i)], &self[(row, j)]);
+            let new_x = checked_dot(a, x, b, y).ok_or(Overflow)?;
+            let new_y = checked_dot(c, x, d, y).ok_or(Overflow)?;
+            self[(row, i)] = new_x;
+            self[(row, j)] = new_y;
+        }
+        Ok(())
+    }
+
+    fn swap_cols(&mut self, i: usize, j: usize) {
+        for row in 0..self.rows {
+            self.data.swap(row * self.cols + i, row * self.cols + j);
+        }
+    }
+
+    fn negate_row(&mut self, row: usize) -> Result<(), Overflow> {
+        for item in &mut self.data[row * self.cols..(row + 1) * self.cols] {
+            *item = item.checked_neg().ok_or(Overflow)?;
+        }
+        Ok(())
+    }
+
+    fn integer_mul_vec(&self, vec: &[T]) -> Result<Vec<T>, Overflow> {
+        self.iter_rows()
+            .map(|row| {
+                row.iter()
+                    .zip(vec)
+                    .try_fold(T::zero(), |acc, (a, b)| acc.checked_add(&a.checked_mul(b)?))
+            })
+            .collect::<Option<_>>()
+            .ok_or(Overflow)
+    }
+
+    /// Like `bareiss`, but also reports whether an odd number of rows were swapped.
+    fn bareiss_with_parity(&mut self) -> Result<(Vec<usize>, bool), Overflow> {
+        let mut pivot_cols = Vec::new();
+        let mut odd_swaps = false;
+        let mut prev_pivot = T::from(1);
+        for col in 0..self.cols {
+            let row = pivot_cols.len();
+            if row == self.rows {
+                break;
+            }
+            let Some(pivot_row) = (row..self.rows).find(|&r| !self[(r, col)].is_zero()) else {
+                continue;
+            };
+            if pivot_row != row {
+                self.swap_rows(row, pivot_row);
+                odd_swaps = !odd_swaps;
+            }
+            let pivot = self[(row, col)].clone();
+            for other in row + 1..self.rows {
+                let factor = self[(other, col)].clone();
+                for c in col + 1..self.cols {
+                    // Sylvester's identity makes this division exact
+                    let value = pivot
+                        .checked_mul(&self[(other, c)])
+                        .zip(factor.checked_mul(&self[(row, c)]))
+                        .and_then(|(a, b)| a.checked_sub(&b))
+                        .and_then(|value| value.checked_div(&prev_pivot))
+                        .ok_or(Overflow)?;
+                    self[(other, c)] = value;
+                }
+                self[(other, col)] = T::zero();
+            }
+            prev_pivot = pivot;
+            pivot_cols.push(col);
+        }
+        Ok((pivot_cols, odd_swaps))
+    }
+
+    /// Brings the matrix into row echelon form with fraction-free (Bareiss) elimination, so
+    /// every entry stays an integer minor of the input. Returns the pivot column of each
+    /// non-zero row.
+    pub fn bareiss(&mut self) -> Result<Vec<usize>, Overflow> {
+        Ok(self.bareiss_with_parity()?.0)
+    }
+
+    /// Determinant computed with `bareiss`, without any rationals.
+    pub fn bareiss_det(&self) -> Result<T, Overflow> {
+        if self.rows != self.cols {
+            panic!("determinant of non-square matrix");
+        }
+        if self.rows == 0 {
+            return Ok(T::from(1));
+        }
+        let mut mat = self.clone();
+        let (pivot_cols, odd_swaps) = mat.bareiss_with_parity()?;
+        if pivot_cols.len() < self.rows {
+            return Ok(T::zero());
+        }
+        let det = mat[(self.rows - 1, self.cols - 1)].clone();
+        if odd_swaps {
+            det.checked_neg().ok_or(Overflow)
+        } else {
+            Ok(det)
+        }
+    }
+
+    /// Row-style Hermite normal form: `h` is in row echelon form with positive pivots, and the
+    /// entries above each pivot lie in `0..pivot`.
+    pub fn hermite_normal_form(&self) -> Result<HermiteForm<T>, Overflow> {
+        let mut h = self.clone();
+        let mut u = Mat::identity(self.rows);
+        let mut row = 0;
+        for col in 0..self.cols {
+            if row == self.rows {
+                break;
+            }
+            // fold every row below into the pivot row, leaving their gcd there
+            for other in row + 1..self.rows {
+                if !h[(other, col)].is_zero() {
+                    let coeffs = gcd_coeffs(&h[(row, col)], &h[(other, col)])?;
+                    h.combine_rows(row, other, &coeffs)?;
+                    u.combine_rows(row, other, &coeffs)?;
+                }
+            }
+            if h[(row, col)].is_zero() {
+                continue;
+            }
+            if h[(row, col)].is_negative() {
+                h.negate_row(row)?;
+                u.negate_row(row)?;
+            }
+            let pivot = h[(row, col)].clone();
+            for above in 0..row {
+                let (quotient, _) = floor_div_rem(&h[(above, col)], &pivot);
+                if !quotient.is_zero() {
+                    h.sub_n_rows_from_row(above, &quotient, row)?;
+                    u.sub_n_rows_from_row(above, &quotient, row)?;
+                }
+            }
+            row += 1;
+        }
+        Ok(HermiteForm { h, u })
+    }
+
+    /// Smith normal form: `s` is diagonal with non-negative entries, each dividing the next,
+    /// and the non-zero ones first.
+    pub fn smith_normal_form(&self) -> Result<SmithForm<T>, Overflow> {
+        let mut s = self.clone();
+        let mut u = Mat::identity(self.rows);
+        let mut v = Mat::identity(self.cols);
+        for t in 0..self.rows.min(self.cols) {
+            let Some((pivot_row, pivot_col)) = (t..self.rows)
+                .flat_map(|row| (t..self.cols).map(move |col| (row, col)))
+                .find(|&pos| !s[pos].is_zero())
+            else {
+                break;
+            };
+            s.swap_rows(t, pivot_row);
+            u.swap_rows(t, pivot_row);
+            s.swap_cols(t, pivot_col);
+            v.swap_cols(t, pivot_col);
+            loop {
+                for row in t + 1..self.rows {
+                    if !s[(row, t)].is_zero() {
+                        let coeffs = gcd_coeffs(&s[(t, t)], &s[(row, t)])?;
+                        s.combine_rows(t, row, &coeffs)?;
+                        u.combine_rows(t, row, &coeffs)?;
+                    }
+                }
+                for col in t + 1..self.cols {
+                    if !s[(t, col)].is_zero() {
+                        let coeffs = gcd_coeffs(&s[(t, t)], &s[(t, col)])?;
+                        s.combine_cols(t, col, &coeffs)?;
+                        v.combine_cols(t, col, &coeffs)?;
+                    }
+                }
+                // the column operations can refill the pivot column
+                if (t + 1..self.rows).any(|row| !s[(row, t)].is_zero()) {
+                    continue;
+                }
+                // the pivot has to divide the rest, otherwise fold an offending row into it
+                let pivot = s[(t, t)].clone();
+                let not_divisible = (t + 1..self.rows).find(|&row| {
+                    (t + 1..self.cols).any(|col| {
+                        !s[(row, col)]
+                            .checked_rem(&pivot)
+                            .is_some_and(|rem| rem.is_zero())
+                    })
+                });
+                match not_divisible {
+                    Some(row) => {
+                        let coeffs = [T::from(1), T::from(1), T::zero(), T::from(1)];
+                        s.combine_rows(t, row, &coeffs)?;
+                        u.combine_rows(t, row, &coeffs)?;
+                    }
+                    None => break,
+                }
+            }
+            if s[(t, t)].is_negative() {
+                s.negate_row(t)?;
+                u.negate_row(t)?;
+            }
+        }
+        Ok(SmithForm { u, s, v })
+    }
+
+    /// Basis of the integer vectors `x` with `self * x == 0`. Every integer solution is an
+    /// integer combination of the basis.
+    pub fn integer_null_space(&self) -> Result<Vec<Vec<T>>, Overflow> {
+        let HermiteForm { h, u } = self.transpose().hermite_normal_form()?;
+        Ok((0..h.rows)
+            .filter(|&row| h.row(row).iter().all(|value| value.is_zero()))
+            .map(|row| u.row(row).to_vec())
+            .collect())
+    }
+
+    /// Solves the system whose right-hand side is the last column over the integers. The
+    /// `null_space` of an affine solution is a lattice basis, so only integer combinations of
+    /// it give integer solutions.
+    pub fn solve_diophantine(&self) -> Result<Solutions<T>, Overflow> {
+        if self.cols == 0 {
+            panic!("augmented matrix needs a right-hand side column");
+        }
+        let vars = self.cols - 1;
+        let coefficients = self.view(0..self.rows, 0..vars).to_mat();
+        let rhs: Vec<T> = self.col(vars).cloned().collect();
+        let SmithForm { u, s, v } = coefficients.smith_normal_form()?;
+
+        // with x = v * y the system becomes the diagonal s * y = u * b
+        let rhs = u.integer_mul_vec(&rhs)?;
+        let mut y = vec![T::zero(); vars];
+        let mut rank = 0;
+        for (row, value) in rhs.iter().enumerate() {
+            let divisor = if row < vars {
+                &s[(row, row)]
+            } else {
+                &T::zero()
+            };
+            if divisor.is_zero() {
+                if !value.is_zero() {
+                    return Ok(Solutions::Inconsistent);
+                }
+                continue;
+            }
+            if !value.checked_rem(divisor).ok_or(Overflow)?.is_zero() {
+                return Ok(Solutions::Inconsistent);
+            }
+            y[row] = value.checked_div(divisor).ok_or(Overflow)?;
+            rank += 1;
+        }
+        let particular = v.integer_mul_vec(&y)?;
+        if rank == vars {
+            return Ok(Solutions::Unique(particular));
+        }
+        let null_space = (rank..vars)
+            .map(|col| v.col(col).cloned().collect())
+            .collect();
+        Ok(Solutions::Affine {
+            particular,
+            null_space,
+        })
+    }
+}
+
+impl<T: ZeroExt + OneExt> Mat<T> {
+    pub fn identity(size: usize) -> Mat<T> {
+        let mut data = Vec::with_capacity(size * size);
+        for row in 0..size {
+            data.extend((0..size).map(|col| if row == col { T::one() } else { T::zero() }));
+        }
+        Mat {
+            data: data.into_boxed_slice(),
+            cols: size,
+            rows: size,
+        }
+    }
+}
+
+impl<T: NumExt> Mat<T> {
+    fn zip_with(&self, rhs: &Mat<T>, op: impl Fn(&T, &T) -> Option<T>) -> Result<Mat<T>, Overflow> {
+        if self.rows != rhs.rows || self.cols != rhs.cols {
+            panic!("matrix dimensions don't match");
+        }
+        let data: Option<Vec<T>> = self
+            .data
+            .iter()
+            .zip(&rhs.data)
+            .map(|(a, b)| op(a, b))
+            .collect();
+        Ok(Mat {
+            data: data.ok_or(Overflow)?.into_boxed_slice(),
+            cols: self.cols,
+            rows: self.rows,
+        })
+    }
+
+    fn map_checked(&self, op: impl Fn(&T) -> Option<T>) -> Result<Mat<T>, Overflow> {
+        let data: Option<Vec<T>> = self.data.iter().map(op).collect();
+        Ok(Mat {
+            data: data.ok_or(Overflow)?.into_boxed_slice(),
+            cols: self.cols,
+            rows: self.rows,
+        })
+    }
+
+    /// Panics if the dimensions don't match.
+    pub fn checked_add(&self, rhs: &Mat<T>) -> Result<Mat<T>, Overflow> {
+        self.zip_with(rhs, T::checked_add)
+    }
+
+    /// Panics if the dimensions don't match.
+    pub fn checked_sub(&self, rhs: &Mat<T>) -> Result<Mat<T>, Overflow> {
+        self.zip_with(rhs, T::checked_sub)
+    }
+
+    /// Matrix product, panics if `self` doesn't have as many columns as `rhs` has rows.
+    pub fn checked_mul(&self, rhs: &Mat<T>) -> Result<Mat<T>, Overflow> {
+        if self.cols != rhs.rows {
+            panic!("matrix dimensions don't match");
+        }
+        let mut data = Vec::with_capacity(self.rows * rhs.cols);
+        for row in self.iter_rows() {
+            for col in 0..rhs.cols {
+                let mut sum = T::zero();
+                for (a, b) in row.iter().zip(rhs.col(col)) {
+                    let product = a.checked_mul(b).ok_or(Overflow)?;
+                    sum = sum.checked_add(&product).ok_or(Overflow)?;
+                }
+                data.push(sum);
+            }
+        }
+        Ok(Mat {
+            data: data.into_boxed_slice(),
+            cols: rhs.cols,
+            rows: self.rows,
+        })
+    }
+
+    pub fn checked_mul_scalar(&self, scalar: &T) -> Result<Mat<T>, Overflow> {
+        self.map_checked(|v| v.checked_mul(scalar))
+    }
+
+    /// `self` to the power of `exp` by repeated squaring, panics if the matrix isn't square.
+    pub fn pow(&self, mut exp: u64) -> Result<Mat<T>, Overflow> {
+        if self.rows != self.cols {
+            panic!("matrix is not square");
+        }
+        let mut result = Mat::identity(self.rows);
+        let mut base = self.clone();
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = result.checked_mul(&base)?;
+            }
+            exp >>= 1;
+            if exp > 0 {
+                base = base.checked_mul(&base)?;
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl<T: FieldExt> Mat<T> {
+    pub fn checked_div_scalar(&self, scalar: &T) -> Result<Mat<T>, Overflow> {
+        self.map_checked(|v| v.checked_div(scalar))
+    }
+
+    /// Determinant by Gaussian elimination, panics if the matrix isn't square. Integer
+    /// matrices have `bareiss_det` instead.
+    pub fn det(&self) -> Result<T, Overflow> {
+        if self.rows != self.cols {
+            panic!("matrix is not square");
+        }
+        let mut mat = self.clone();
+        let mut det = T::one();
+        for col in 0..mat.cols {
+            let Some(row) = mat.highest_row_below(col, col)? else {
+                return Ok(T::zero());
+            };
+            if row != col {
+                mat.swap_rows(row, col);
+                det = T::zero().checked_sub(&det).ok_or(Overflow)?;
+            }
+            let pivot = mat[(col, col)].clone();
+            det = det.checked_mul(&pivot).ok_or(Overflow)?;
+            for other_row in col + 1..mat.rows {
+                let val = &mat[(other_row, col)];
+                if !val.is_zero() {
+                    let factor = val.checked_div(&pivot).ok_or(Overflow)?;
+                    mat.sub_n_rows_from_row(other_row, &factor, col)?;
+                }
+            }
+        }
+        Ok(det)
+    }
+
+    /// `None` if the matrix is singular, panics if it isn't square.
+    pub fn inverse(&self) -> Result<Option<Mat<T>>, Overflow> {
+        if self.rows != self.cols {
+            panic!("matrix is not square");
+        }
+        let size = self.rows;
+        let identity = Mat::identity(size);
+        let mut augmented = Mat::with_value(2 * size, size, T::zero());
+        for row in 0..size {
+            augmented.data[row * 2 * size..(2 * row + 1) * size].clone_from_slice(self.row(row));
+            augmented.data[(2 * row + 1) * size..(2 * row + 2) * size]
+                .clone_from_slice(identity.row(row));
+        }
+        let pivot_cols = augmented.to_rref(false)?;
+        if !pivot_cols.into_iter().eq(0..size) {
+            return Ok(None);
+        }
+        Ok(Some(augmented.view(0..size, size..2 * size).to_mat()))
+    }
+
+    pub fn rank(&self) -> Result<usize, Overflow> {
+        Ok(self.clone().to_rref(false)?.len())
+    }
+}
+
+impl<T: NumExt> Add for &Mat<T> {
+    type Output = Mat<T>;
+
+    fn add(self, rhs: &Mat<T>) -> Mat<T> {
+        self.checked_add(rhs).expect("Mat overflow")
+    }
+}
+
+impl<T: NumExt> Sub for &Mat<T> {
+    type Output = Mat<T>;
+
+    fn sub(self, rhs: &Mat<T>) -> Mat<T> {
+        self.checked_sub(rhs).expect("Mat overflow")
+    }
+}
+
+impl<T: NumExt> Mul for &Mat<T> {
+    type Output = Mat<T>;
+
+    fn mul(self, rhs: &Mat<T>) -> Mat<T> {
+        self.checked_mul(rhs).expect("Mat overflow")
+    }
+}
+
+impl<T: NumExt> Mul<T> for &Mat<T> {
+    type Output = Mat<T>;
+
+    fn mul(self, rhs: T) -> Mat<T> {
+        self.checked_mul_scalar(&rhs).expect("Mat overflow")
+    }
+}
+
+impl<T: FieldExt> Div<T> for &Mat<T> {
+    type Output = Mat<T>;
+
+    fn div(self, rhs: T) -> Mat<T> {
+        self.checked_div_scalar(&rhs)
+            .expect("Mat overflow or division by zero")
+    }
+}
+
+impl<T: Default> Mat<T> {
+    pub fn new(cols: usize, rows: usize) -> Mat<T> {
+        let mut data = Vec::new();
+        data.resize_with(cols * rows, T::default);
+        Mat {
+            data: data.into_boxed_slice(),
+            cols,
+            rows,
+        }
+    }
+}
+
+impl<T> Mat<T> {
+    fn swap_rows(&mut self, i: usize, j: usize) {
+        let lower = std::cmp::min(i, j);
+        let higher = std::cmp::max(i, j);
+        if lower != higher && higher < self.rows {
+            let (first, second) = self.data.split_at_mut(higher * self.cols);
+            first[lower * self.cols..(lower + 1) * self.cols]
+                .swap_with_slice(&mut second[0..self.cols]);
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+    pub fn row(&self, row: usize) -> &[T] {
+        if row >= self.rows {
+            panic!("index out of bounds");
+        }
+        &self.data[row * self.cols..(row + 1) * self.cols]
+    }
+
+    pub fn col(&self, col: usize) -> impl Iterator<Item = &T> {
+        if col >= self.cols {
+            panic!("index out of bounds");
+        }
+        self.data.iter().skip(col).step_by(self.cols)
+    }
+
+    pub fn iter_rows(&self) -> impl Iterator<Item = &[T]> {
+        (0..self.rows).map(|row| self.row(row))
+    }
+
+    pub fn iter_cols(&self) -> impl Iterator<Item = impl Iterator<Item = &T>> {
+        (0..self.cols).map(|col| self.col(col))
+    }
+
+    /// Borrows the sub-matrix made of the given row and column ranges.
+    pub fn view(&self, rows: Range<usize>, cols: Range<usize>) -> MatView<'_, T> {
+        if rows.start > rows.end
+            || rows.end > self.rows
+            || cols.start > cols.end
+            || cols.end > self.cols
+        {
+            panic!("index out of bounds");
+        }
+        MatView {
+            mat: self,
+            rows,
+            cols,
+        }
+    }
+}
+
+impl<T: Clone> Mat<T> {
+    pub fn from_array<const R: usize, const C: usize>(array: &[[T; C]; R]) -> Mat<T> {
+        let mut data = Vec::with_capacity(R * C);
+        for row in array {
+            data.extend_from_slice(row);
+        }
+        Mat {
+            data: data.into_boxed_slice(),
+            cols: C,
+            rows: R,
+        }
+    }
+    pub fn with_value(cols: usize, rows: usize, val: T) -> Mat<T> {
+        Mat {
+            data: vec![val; cols * rows].into_boxed_slice(),
+            cols,
+            rows,
+        }
+    }
+
+    pub fn transpose(&self) -> Mat<T> {
+        Mat {
+            data: self.iter_cols().flatten().cloned().collect(),
+            cols: self.rows,
+            rows: self.cols,
+        }
+    }
+}
+
+/// A borrowed rectangular part of a `Mat`, indexed relative to its top left corner.
+#[derive(Debug)]
+pub struct MatView<'a, T> {
+    mat: &'a Mat<T>,
+    rows: Range<usize>,
+    cols: Range<usize>,
+}
+
+impl<T> Clone for MatView<'_, T> {
+    fn clone(&self) -> Self {
+        MatView {
+            mat: self.mat,
+            rows: self.rows.clone(),
+            cols: self.cols.clone(),
+        }
+    }
+}
+
+impl<'a, T> MatView<'a, T> {
+    pub fn rows(&self) -> usize {
+        self.rows.len()
+    }
+    pub fn cols(&self) -> usize {
+        self.cols.len()
+    }
+    pub fn row(&self, row: usize) -> &'a [T] {
+        if row >= self.rows() {
+            panic!("index out of bounds");
+        }
+        &self.mat.row(self.rows.start + row)[self.cols.clone()]
+    }
+    pub fn iter_rows(&self) -> impl Iterator<Item = &'a [T]> {
+        let view = self.clone();
+        (0..self.rows()).map(move |row| view.row(row))
+    }
+    pub fn view(&self, rows: Range<usize>, cols: Range<usize>) -> MatView<'a, T> {
+        if rows.start > rows.end
+            || rows.end > self.rows()
+            || cols.start > cols.end
+            || cols.end > self.cols()
+        {
+            panic!("index out of bounds");
+        }
+        MatView {
+            mat: self.mat,
+            rows: self.rows.start + rows.start..self.rows.start + rows.end,
+            cols: self.cols.start + cols.start..self.cols.start + cols.end,
+        }
+    }
+}
+
+impl<T: Clone> MatView<'_, T> {
+    pub fn to_mat(&self) -> Mat<T> {
+        Mat {
+            data: self.iter_rows().flatten().cloned().collect(),
+            cols: self.cols(),
+            rows: self.rows(),
+        }
+    }
+}
+
+impl<T> Index<(usize, usize)> for MatView<'_, T> {
+    type Output = T;
+
+    fn index(&self, index: (usize, usize)) -> &T {
+        if index.0 < self.rows() && index.1 < self.cols() {
+            &self.mat[(self.rows.start + index.0, self.cols.start + index.1)]
+        } else {
+            panic!("out of bounds!");
+        }
+    }
+}
+
+impl<T> Index<(usize, usize)> for Mat<T> {
+    type Output = T;
+
+    fn index(&self, index: (usize, usize)) -> &T {
+        if index.0 < self.rows && index.1 < self.cols {
+            &self.data[index.0 * self.cols + index.1]
+        } else {
+            panic!("out of bounds!");
+        }
+    }
+}
+
+impl<T> IndexMut<(usize, usize)> for Mat<T> {
+    fn index_mut(&mut self, index: (usize, usize)) -> &mut T {
+        if index.0 < self.rows && index.1 < self.cols {
+            &mut self.data[index.0 * self.cols + index.1]
+        } else {
+            panic!("out of bounds!");
+        }
+    }
+}
+
+impl<T> Display for Mat<T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.rows > 0 {
+            for row in 0..self.rows {
+                f.write_str(if row == 0 { "[" } else { " " })?;
+                for col in 0..self.cols {
+                    if col > 0 {
+                        write!(f, ", ")?;
+                    }
+                    self[(row, col)].fmt(f)?;
+                }
+
+                f.write_str(if row == self.rows - 1 { "]" } else { "\n" })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A matrix over GF(2), with each row packed into `u64` words.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct BitMat {
+    words: Vec<u64>,
+    cols: usize,
+    rows: usize,
+    words_per_row: usize,
+}
+
+/// Packed particular solution and null space basis of a `BitMat` system.
+type PackedSolution = (Vec<u64>, Vec<Vec<u64>>);
+
+/// Sets a bit in a packed vector.
+fn set_bit(words: &mut [u64], bit: usize) {
+    words[bit / 64] |= 1 << (bit % 64);
+}
+
+fn unpack_bits(words: &[u64], len: usize) -> Vec<bool> {
+    (0..len)
+        .map(|bit| words[bit / 64] >> (bit % 64) & 1 == 1)
+        .collect()
+}
+
+impl BitMat {
+    pub fn new(cols: usize, rows: usize) -> BitMat {
+        let words_per_row = cols.div_ceil(64);
+        BitMat {
+            words: vec![0; words_per_row * rows],
+            cols,
+            rows,
+            words_per_row,
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> bool {
+        if row >= self.rows || col >= self.cols {
+            panic!("out of bounds!");
+        }
+        self.words[row * self.words_per_row + col / 64] >> (col % 64) & 1 == 1
+    }
+
+    pub fn set(&mut self, row: usize, col: usize, value: bool) {
+        if row >= self.rows || col >= self.cols {
+            panic!("out of bounds!");
+        }
+        let word = &mut self.words[row * self.words_per_row + col / 64];
+        if value {
+            *word |= 1 << (col % 64);
+        } else {
+            *word &= !(1 << (col % 64));
+        }
+    }
+
+    fn xor_row_into(&mut self, row: usize, source: usize) {
+        let (row_start, source_start) = (row * self.words_per_row, source * self.words_per_row);
+        for i in 0..self.words_per_row {
+            self.words[row_start + i] ^= self.words[source_start + i];
+        }
+    }
+
+    fn swap_rows(&mut self, i: usize, j: usize) {
+        for word in 0..self.words_per_row {
+            self.words
+                .swap(i * self.words_per_row + word, j * self.words_per_row + word);
+        }
+    }
+
+    /// Brings the matrix into reduced row echelon form and returns the pivot column of each
+    /// non-zero row. If `augmented_mat` is set, the last column is not used as a pivot column.
+    pub fn to_rref(&mut self, augmented_mat: bool) -> Vec<usize> {
+        let max_cols = if augmented_mat {
+            self.cols.saturating_sub(1)
+        } else {
+            self.cols
+        };
+        let mut pivot_cols = Vec::new();
+        for col in 0..max_cols {
+            let pivot_row = pivot_cols.len();
+            if pivot_row == self.rows {
+                break;
+            }
+            let Some(row) = (pivot_row..self.rows).find(|&row| self.get(row, col)) else {
+                continue;
+            };
+            self.swap_rows(pivot_row, row);
+            for other_row in 0..self.rows {
+                if other_row != pivot_row && self.get(other_row, col) {
+                    self.xor_row_into(other_row, pivot_row);
+                }
+            }
+            pivot_cols.push(col);
+        }
+        pivot_cols
+    }
+
+    /// Reduces the system whose right-hand side is the last column, returning the pivot and
+    /// free columns, and the packed particular solution and null space basis if consistent.
+    fn packed_solution(&mut self) -> (Vec<usize>, Vec<usize>, Option<PackedSolution>) {
+        if self.cols == 0 {
+            panic!("augmented matrix needs a right-hand side column");
+        }
+        let pivot_cols = self.to_rref(true);
+        let vars = self.cols - 1;
+        let mut is_pivot = vec![false; vars];
+        for &col in &pivot_cols {
+            is_pivot[col] = true;
+        }
+        let free_cols: Vec<usize> = (0..vars).filter(|&col| !is_pivot[col]).collect();
+        if (pivot_cols.len()..self.rows).any(|row| self.get(row, vars)) {
+            return (pivot_cols, free_cols, None);
+        }
+
+        let words = vars.div_ceil(64);
+        let mut particular = vec![0; words];
+        for (row, &col) in pivot_cols.iter().enumerate() {
+            if self.get(row, vars) {
+                set_bit(&mut particular, col);
+            }
+        }
+        let null_space = free_cols
+            .iter()
+            .map(|&free_col| {
+                let mut basis = vec![0; words];
+                set_bit(&mut basis, free_col);
+                for (row, &col) in pivot_cols.iter().enumerate() {
+                    if self.get(row, free_col) {
+                        set_bit(&mut basis, col);
+                    }
+                }
+                basis
+            })
+            .collect();
+        (pivot_cols, free_cols, Some((particular, null_space)))
+    }
+
+    /// Solves the system whose right-hand side is the last column, reducing the matrix to
+    /// reduced row echelon form in place.
+    pub fn solve_augmented(&mut self) -> AugmentedSolution<bool> {
+        let vars = self.cols.saturating_sub(1);
+        let (pivot_cols, free_cols, solution) = self.packed_solution();
+        let solutions = match solution {
+            None => Solutions::Inconsistent,
+            Some((particular, null_space)) if null_space.is_empty() => {
+                Solutions::Unique(unpack_bits(&particular, vars))
+            }
+            Some((particular, null_space)) => Solutions::Affine {
+                particular: unpack_bits(&particular, vars),
+                null_space: null_space
+                    .iter()
+                    .map(|basis| unpack_bits(basis, vars))
+                    .collect(),
+            },
+        };
+        AugmentedSolution {
+            pivot_cols,
+            free_cols,
+            solutions,
+        }
+    }
+
+    /// Every solution of the system whose right-hand side is the last column. The iterator
+    /// counts its steps in a `u64`, so this fails if there are 64 or more free variables.
+    pub fn solutions(&self) -> Result<BitSolutions, Overflow> {
+        let (_, _, solution) = self.clone().packed_solution();
+        let (current, null_space) = match solution {
+            Some((particular, null_space)) => (Some(particular), null_space),
+            None => (None, Vec::new()),
+        };
+        if null_space.len() >= 64 {
+            return Err(Overflow);
+        }
+        Ok(BitSolutions {
+            current,
+            null_space,
+            step: 0,
+            vars: self.cols - 1,
+        })
+    }
+
+    /// A solution with the fewest set bits, `None` if there is none. Decides one free variable
+    /// at a time and prunes once the bits that can no longer change weigh as much as the best
+    /// solution so far, so it's exponential in the free variables only in the worst case.
+    pub fn min_weight_solution(&self) -> Option<Vec<bool>> {
+        let mut reduced = self.clone();
+        let (pivot_cols, free_cols, solution) = reduced.packed_solution();
+        let (particular, null_space) = solution?;
+        let vars = self.cols - 1;
+        // a bit is fixed once every free variable it depends on is decided
+        let mut fixed_from = vec![0; vars];
+        for (i, &free_col) in free_cols.iter().enumerate() {
+            fixed_from[free_col] = i + 1;
+        }
+        for (row, &col) in pivot_cols.iter().enumerate() {
+            fixed_from[col] = (0..free_cols.len())
+                .rev()
+                .find(|&i| reduced.get(row, free_cols[i]))
+                .map_or(0, |i| i + 1);
+        }
+        let mut fixed = vec![vec![0; particular.len()]; free_cols.len() + 1];
+        for (col, &from) in fixed_from.iter().enumerate() {
+            for mask in &mut fixed[from..] {
+                set_bit(mask, col);
+            }
+        }
+
+        let mut search = MinWeightSearch {
+            null_space,
+            fixed,
+            best: None,
+        };
+        search.search(&mut particular.clone(), 0);
+        search.best.map(|(_, words)| unpack_bits(&words, vars))
+    }
+}
+
+/// Depth-first branch and bound over the free variables of a reduced `BitMat` system.
+struct MinWeightSearch {
+    null_space: Vec<Vec<u64>>,
+    // the bits that can't change once the first `depth` free variables are decided
+    fixed: Vec<Vec<u64>>,
+    best: Option<(u32, Vec<u64>)>,
+}
+
+impl MinWeightSearch {
+    fn search(&mut self, current: &mut Vec<u64>, depth: usize) {
+        let bound = current
+            .iter()
+            .zip(&self.fixed[depth])
+            .map(|(word, mask)| (word & mask).count_ones())
+            .sum();
+        if self
+            .best
+            .as_ref()
+            .is_some_and(|(best_weight, _)| bound >= *best_weight)
+        {
+            return;
+        }
+        if depth == self.null_space.len() {
+            self.best = Some((bound, current.clone()));
+            return;
+        }
+        // leaving the free variable unset first finds a light solution early
+        self.search(current, depth + 1);
+        for (word, basis_word) in current.iter_mut().zip(&self.null_space[depth]) {
+            *word ^= basis_word;
+        }
+        self.search(current, depth + 1);
+        for (word, basis_word) in current.iter_mut().zip(&self.null_space[depth]) {
+            *word ^= basis_word;
+        }
+    }
+}
+
+impl Display for BitMat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for row in 0..self.rows {
+            if row > 0 {
+                f.write_str("\n")?;
+            }
+            for col in 0..self.cols {
+                f.write_str(if self.get(row, col) { "1" } else { "0" })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Iterator over the solutions of a `BitMat` system, visiting the null space in Gray code
+/// order so each step flips a single basis vector.
+pub struct BitSolutions {
+    current: Option<Vec<u64>>,
+    null_space: Vec<Vec<u64>>,
+    step: u64,
+    vars: usize,
+}
+
+impl Iterator for BitSolutions {
+    type Item = Vec<bool>;
+
+    fn next(&mut self) -> Option<Vec<bool>> {
+        let current = self.current.as_mut()?;
+        let solution = unpack_bits(current, self.vars);
+        self.step += 1;
+        if self.step == 1 << self.null_space.len() {
+            self.current = None;
+        } else {
+            let basis = &self.null_space[self.step.trailing_zeros() as usize];
+            for (word, basis_word) in current.iter_mut().zip(basis) {
+                *word ^= basis_word;
+            }
+        }
+        Some(solution)
+    }
+}
+
+/// An integer type a `Rational` can be built on.
+pub trait RationalInt:
+    CheckedExt + ZeroExt + OneExt + From<i32> + Clone + Ord + Hash + std::fmt::Debug + Display
+{
+    fn is_negative(&self) -> bool;
+    fn checked_neg(&self) -> Option<Self>;
+    /// Remainder of truncating division, `None` if `rhs` is zero or the division overflows.
+    fn checked_rem(&self, rhs: &Self) -> Option<Self>;
+    fn to_f64(&self) -> f64;
+}
+
+macro_rules! impl_rational_int {
+    ($($t:ty),*) => {
+        $(
+            impl RationalInt for $t {
+                fn is_negative(&self) -> bool {
+                    *self < 0
+                }
+
+                fn checked_neg(&self) -> Option<Self> {
+                    <$t>::checked_neg(*self)
+                }
+
+                fn checked_rem(&self, rhs: &Self) -> Option<Self> {
+                    <$t>::checked_rem(*self, *rhs)
+                }
+
+                fn to_f64(&self) -> f64 {
+                    *self as f64
+                }
+            }
+        )*
+    };
+}
+
+impl_rational_int!(i64, i128);
+
+/// An arbitrary-precision signed integer, for `Rational`s that outgrow `i128`.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct BigInt {
+    negative: bool,
+    // little-endian base 2^32 digits without trailing zeros, so zero is empty (and not negative)
+    magnitude: Vec<u32>,
+}
+
+impl BigInt {
+    fn from_parts(negative: bool, mut magnitude: Vec<u32>) -> BigInt {
+        while magnitude.last() == Some(&0) {
+            magnitude.pop();
+        }
+        BigInt {
+            negative: negative && !magnitude.is_empty(),
+            magnitude,
+        }
+    }
+
+    fn cmp_magnitudes(a: &[u32], b: &[u32]) -> std::cmp::Ordering {
+        a.len()
+            .cmp(&b.len())
+            .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+    }
+
+    fn add_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+        let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+        let mut result = Vec::with_capacity(long.len() + 1);
+        let mut carry = 0u64;
+        for (i, &digit) in long.iter().enumerate() {
+            let sum = digit as u64 + short.get(i).copied().unwrap_or(0) as u64 + carry;
+            result.push(sum as u32);
+            carry = sum >> 32;
+        }
+        result.push(carry as u32);
+        result
+    }
+
+    /// `a - b`, requires `a >= b`.
+    fn sub_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+        let mut result = Vec::with_capacity(a.len());
+        let mut borrow = 0i64;
+        for (i, &digit) in a.iter().enumerate() {
+            let diff = digit as i64 - b.get(i).copied().unwrap_or(0) as i64 - borrow;
+            result.push(diff as u32);
+            borrow = (diff < 0) as i64;
+        }
+        result
+    }
+
+    fn mul_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+        let mut result = vec![0u32; a.len() + b.len()];
+        for (i, &x) in a.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &y) in b.iter().enumerate() {
+                let product = x as u64 * y as u64 + result[i + j] as u64 + carry;
+                result[i + j] = product as u32;
+                carry = product >> 32;
+            }
+            result[i + b.len()] = carry as u32;
+        }
+        result
+    }
+
+    fn shl_bits(a: &[u32], shift: u32) -> Vec<u32> {
+        let mut result = Vec::with_capacity(a.len() + 1);
+        let mut carry = 0;
+        for &digit in a {
+            result.push((digit << shift) | carry);
+            carry = if shift == 0 { 0 } else { digit >> (32 - shift) };
+        }
+        result.push(carry);
+        result
+    }
+
+    fn shr_bits(a: &[u32], shift: u32) -> Vec<u32> {
+        (0..a.len())
+            .map(|i| {
+                let high = match a.get(i + 1) {
+                    Some(&next) if shift > 0 => next << (32 - shift),
+                    _ => 0,
+                };
+                (a[i] >> shift) | high
+            })
+            .collect()
+    }
+
+    /// Quotient and remainder of `a / b`, requires a non-zero `b` without trailing zeros.
+    fn divrem_magnitudes(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+        if Self::cmp_magnitudes(a, b) == std::cmp::Ordering::Less {
+            return (Vec::new(), a.to_vec());
+        }
+        if let [divisor] = b {
+            let divisor = *divisor as u64;
+            let mut quotient = vec![0u32; a.len()];
+            let mut rem = 0u64;
+            for i in (0..a.len()).rev() {
+                let cur = (rem << 32) | a[i] as u64;
+                quotient[i] = (cur / divisor) as u32;
+                rem = cur % divisor;
+            }
+            return (quotient, vec![rem as u32]);
+        }
+
+        // Knuth's algorithm D, on operands shifted so the divisor's top digit has its high bit set
+        let shift = b[b.len() - 1].leading_zeros();
+        let mut v = Self::shl_bits(b, shift);
+        v.pop();
+        let mut u = Self::shl_bits(a, shift);
+        let n = v.len();
+        let m = a.len() - n;
+        let mut quotient = vec![0u32; m + 1];
+        for j in (0..=m).rev() {
+            let num = ((u[j + n] as u64) << 32) | u[j + n - 1] as u64;
+            let mut qhat = num / v[n - 1] as u64;
+            let mut rhat = num % v[n - 1] as u64;
+            while qhat >> 32 != 0 || qhat * v[n - 2] as u64 > ((rhat << 32) | u[j + n - 2] as u64) {
+                qhat -= 1;
+                rhat += v[n - 1] as u64;
+                if rhat >> 32 != 0 {
+                    break;
+                }
+            }
+
+            let mut borrow = 0i64;
+            let mut carry = 0u64;
+            for i in 0..n {
+                let product = qhat * v[i] as u64 + carry;
+                carry = product >> 32;
+                let diff = u[i + j] as i64 - borrow - (product & 0xffff_ffff) as i64;
+                u[i + j] = diff as u32;
+                borrow = (diff < 0) as i64;
+            }
+            let diff = u[j + n] as i64 - borrow - carry as i64;
+            u[j + n] = diff as u32;
+
+            // qhat was one too large, add the divisor back
+            if diff < 0 {
+                qhat -= 1;
+                let mut carry = 0u64;
+                for i in 0..n {
+                    let sum = u[i + j] as u64 + v[i] as u64 + carry;
+                    u[i + j] = sum as u32;
+                    carry = sum >> 32;
+                }
+                u[j + n] = u[j + n].wrapping_add(carry as u32);
+            }
+            quotient[j] = qhat as u32;
+        }
+        (quotient, Self::shr_bits(&u[..n], shift))
+    }
+
+    fn add_signed(&self, negative: bool, magnitude: &[u32]) -> BigInt {
+        if self.negative == negative {
+            return BigInt::from_parts(negative, Self::add_magnitudes(&self.magnitude, magnitude));
+        }
+        match Self::cmp_magnitudes(&self.magnitude, magnitude) {
+            std::cmp::Ordering::Less => {
+                BigInt::from_parts(negative, Self::sub_magnitudes(magnitude, &self.magnitude))
+            }
+            _ => BigInt::from_parts(
+                self.negative,
+                Self::sub_magnitudes(&self.magnitude, magnitude),
+            ),
+        }
+    }
+}
+
+impl From<i128> for BigInt {
+    fn from(value: i128) -> Self {
+        let abs = value.unsigned_abs();
+        let magnitude = (0..4).map(|i| (abs >> (32 * i)) as u32).collect();
+        BigInt::from_parts(value < 0, magnitude)
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(value: i64) -> Self {
+        BigInt::from(value as i128)
+    }
+}
+
+impl From<i32> for BigInt {
+    fn from(value: i32) -> Self {
+        BigInt::from(value as i128)
+    }
+}
+
+impl TryFrom<BigInt> for u64 {
+    type Error = Overflow;
+
+    fn try_from(value: BigInt) -> Result<Self, Overflow> {
+        match value.magnitude[..] {
+            _ if value.negative => Err(Overflow),
+            [] => Ok(0),
+            [low] => Ok(low as u64),
+            [low, high] => Ok((high as u64) << 32 | low as u64),
+            _ => Err(Overflow),
+        }
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => std::cmp::Ordering::Greater,
+            (true, false) => std::cmp::Ordering::Less,
+            (false, false) => Self::cmp_magnitudes(&self.magnitude, &other.magnitude),
+            (true, true) => Self::cmp_magnitudes(&other.magnitude, &self.magnitude),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl ZeroExt for BigInt {
+    fn zero() -> Self {
+        BigInt::default()
+    }
+
+    fn is_zero(&self) -> bool {
+        self.magnitude.is_empty()
+    }
+}
+
+impl OneExt for BigInt {
+    fn one() -> Self {
+        BigInt::from(1)
+    }
+}
+
+impl CheckedExt for BigInt {
+    fn checked_add(&self, rhs: &Self) -> Option<Self> {
+        Some(self.add_signed(rhs.negative, &rhs.magnitude))
+    }
+
+    fn checked_sub(&self, rhs: &Self) -> Option<Self> {
+        Some(self.add_signed(!rhs.negative, &rhs.magnitude))
+    }
+
+    fn checked_mul(&self, rhs: &Self) -> Option<Self> {
+        Some(BigInt::from_parts(
+            self.negative != rhs.negative,
+            Self::mul_magnitudes(&self.magnitude, &rhs.magnitude),
+        ))
+    }
+
+    fn checked_div(&self, rhs: &Self) -> Option<Self> {
+        if rhs.is_zero() {
+            return None;
+        }
+        let (quotient, _) = Self::divrem_magnitudes(&self.magnitude, &rhs.magnitude);
+        Some(BigInt::from_parts(self.negative != rhs.negative, quotient))
+    }
+}
+
+impl RationalInt for BigInt {
+    fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    fn checked_neg(&self) -> Option<Self> {
+        Some(BigInt::from_parts(!self.negative, self.magnitude.clone()))
+    }
+
+    fn checked_rem(&self, rhs: &Self) -> Option<Self> {
+        if rhs.is_zero() {
+            return None;
+        }
+        let (_, rem) = Self::divrem_magnitudes(&self.magnitude, &rhs.magnitude);
+        Some(BigInt::from_parts(self.negative, rem))
+    }
+
+    fn to_f64(&self) -> f64 {
+        let abs = self
+            .magnitude
+            .iter()
+            .rev()
+            .fold(0.0, |acc, &digit| acc * 4294967296.0 + digit as f64);
+        if self.negative { -abs } else { abs }
+    }
+}
+
+impl Display for BigInt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const CHUNK: u32 = 1_000_000_000;
+        let mut chunks = Vec::new();
+        let mut rest = self.magnitude.clone();
+        while !rest.is_empty() {
+            let (quotient, rem) = Self::divrem_magnitudes(&rest, &[CHUNK]);
+            chunks.push(rem[0]);
+            rest = BigInt::from_parts(false, quotient).magnitude;
+        }
+        let mut digits = chunks.pop().unwrap_or(0).to_string();
+        for chunk in chunks.iter().rev() {
+            digits.push_str(&format!("{:09}", chunk));
+        }
+        f.pad_integral(!self.negative, "", &digits)
+    }
+}
+
+fn checked_gcd<T: RationalInt>(mut a: T, mut b: T) -> Option<T> {
+    while !b.is_zero() {
+        let rem = a.checked_rem(&b)?;
+        a = b;
+        b = rem;
+    }
+    if a.is_negative() {
+        a.checked_neg()
+    } else {
+        Some(a)
+    }
+}
+
+/// `a * x + b * y`, `None` on overflow.
+fn checked_dot<T: CheckedExt>(a: &T, x: &T, b: &T, y: &T) -> Option<T> {
+    a.checked_mul(x)?.checked_add(&b.checked_mul(y)?)
+}
+
+/// Coefficients `[s, t, -b / g, a / g]` of a unimodular operation turning `(a, b)` into
+/// `(g, 0)`, where `g = s * a + t * b` is a gcd. If `a` divides `b` then `g` is `a`, so the
+/// first row or column is left alone.
+fn gcd_coeffs<T: RationalInt>(a: &T, b: &T) -> Result<[T; 4], Overflow> {
+    if !a.is_zero() && b.checked_rem(a).is_some_and(|rem| rem.is_zero()) {
+        let quotient = b.checked_div(a).ok_or(Overflow)?;
+        let neg_quotient = quotient.checked_neg().ok_or(Overflow)?;
+        return Ok([T::from(1), T::zero(), neg_quotient, T::from(1)]);
+    }
+    let (mut old_r, mut r) = (a.clone(), b.clone());
+    let (mut old_s, mut s) = (T::from(1), T::zero());
+    let (mut old_t, mut t) = (T::zero(), T::from(1));
+    while !r.is_zero() {
+        let quotient = old_r.checked_div(&r).ok_or(Overflow)?;
+        let step = |old: &T, new: &T| {
+            quotient
+                .checked_mul(new)
+                .and_then(|product| old.checked_sub(&product))
+                .ok_or(Overflow)
+        };
+        (old_r, r) = (r.clone(), step(&old_r, &r)?);
+        (old_s, s) = (s.clone(), step(&old_s, &s)?);
+        (old_t, t) = (t.clone(), step(&old_t, &t)?);
+    }
+    if old_r.is_negative() {
+        old_r = old_r.checked_neg().ok_or(Overflow)?;
+        old_s = old_s.checked_neg().ok_or(Overflow)?;
+        old_t = old_t.checked_neg().ok_or(Overflow)?;
+    }
+    let neg_b = b.checked_neg().ok_or(Overflow)?;
+    let c = neg_b.checked_div(&old_r).ok_or(Overflow)?;
+    let d = a.checked_div(&old_r).ok_or(Overflow)?;
+    Ok([old_s, old_t, c, d])
+}
+
+/// Floor division by a positive `divisor`, returning quotient and non-negative remainder.
+fn floor_div_rem<T: RationalInt>(value: &T, divisor: &T) -> (T, T) {
+    let mut quotient = value.checked_div(divisor).expect("divisor is positive");
+    let mut rem = value.checked_rem(divisor).expect("divisor is positive");
+    if rem.is_negative() {
+        // the remainder is non-zero, so the divisor is at least 2 and this can't overflow
+        quotient = quotient.checked_sub(&T::from(1)).unwrap();
+        rem = rem.checked_add(divisor).unwrap();
+    }
+    (quotient, rem)
+}
+
+/// Exact fraction in lowest terms with a positive denominator, backed by the integer type `T`.
+///
+/// Every constructor returns this canonical form, so equal values compare and hash equal. The
+/// operators panic on overflow, use the `CheckedExt` methods to handle it instead.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct Rational<T = i64> {
+    numerator: T,
+    denominator: T,
+}
+
+impl<T: RationalInt> Rational<T> {
+    /// `numerator / denominator` in lowest terms, `None` if the denominator is zero or the
+    /// result does not fit `T`.
+    pub fn checked_new(numerator: T, denominator: T) -> Option<Rational<T>> {
+        if denominator.is_zero() {
+            return None;
+        }
+        let gcd = checked_gcd(numerator.clone(), denominator.clone())?;
+        let mut numerator = numerator.checked_div(&gcd)?;
+        let mut denominator = denominator.checked_div(&gcd)?;
+        if denominator.is_negative() {
+            numerator = numerator.checked_neg()?;
+            denominator = denominator.checked_neg()?;
+        }
+        Some(Rational {
+            numerator,
+            denominator,
+        })
+    }
+
+    /// Panics if the denominator is zero or the result does not fit `T`.
+    pub fn new(numerator: T, denominator: T) -> Rational<T> {
+        Self::checked_new(numerator, denominator).expect("invalid Rational")
+    }
+
+    pub fn from_int<I: Into<T>>(i: I) -> Rational<T> {
+        Rational {
+            numerator: i.into(),
+            denominator: T::from(1),
+        }
+    }
+
+    pub fn to_int(&self) -> Option<T> {
+        if self.denominator == T::from(1) {
+            Some(self.numerator.clone())
+        } else {
+            None
+        }
+    }
+
+    pub fn numerator(&self) -> &T {
+        &self.numerator
+    }
+
+    pub fn denominator(&self) -> &T {
+        &self.denominator
+    }
+
+    pub fn checked_neg(&self) -> Option<Rational<T>> {
+        Some(Rational {
+            numerator: self.numerator.checked_neg()?,
+            denominator: self.denominator.clone(),
+        })
+    }
+
+    /// Largest integer not above the value.
+    pub fn floor(&self) -> Rational<T> {
+        Rational::from_int(floor_div_rem(&self.numerator, &self.denominator).0)
+    }
+
+    /// Smallest integer not below the value.
+    pub fn ceil(&self) -> Rational<T> {
+        let (quotient, rem) = floor_div_rem(&self.numerator, &self.denominator);
+        if rem.is_zero() {
+            Rational::from_int(quotient)
+        } else {
+            // a non-zero remainder means the denominator is at least 2, so this can't overflow
+            Rational::from_int(quotient.checked_add(&T::from(1)).unwrap())
+        }
+    }
+
+    /// `None` for zero, or if the result does not fit `T`.
+    pub fn checked_recip(&self) -> Option<Rational<T>> {
+        if self.numerator.is_zero() {
+            None
+        } else if self.numerator.is_negative() {
+            Some(Rational {
+                numerator: self.denominator.checked_neg()?,
+                denominator: self.numerator.checked_neg()?,
+            })
+        } else {
+            Some(Rational {
+                numerator: self.denominator.clone(),
+                denominator: self.numerator.clone(),
+            })
+        }
+    }
+}
+
+impl<T: RationalInt> CheckedExt for Rational<T> {
+    fn checked_add(&self, rhs: &Self) -> Option<Self> {
+        // scale by the lcm of the denominators to keep intermediates small
+        let gcd = checked_gcd(self.denominator.clone(), rhs.denominator.clone())?;
+        let lhs_scale = rhs.denominator.checked_div(&gcd)?;
+        let rhs_scale = self.denominator.checked_div(&gcd)?;
+        let numerator = self
+            .numerator
+            .checked_mul(&lhs_scale)?
+            .checked_add(&rhs.numerator.checked_mul(&rhs_scale)?)?;
+        Rational::checked_new(numerator, self.denominator.checked_mul(&lhs_scale)?)
+    }
+
+    fn checked_sub(&self, rhs: &Self) -> Option<Self> {
+        self.checked_add(&rhs.checked_neg()?)
+    }
+
+    fn checked_mul(&self, rhs: &Self) -> Option<Self> {
+        // cancel crosswise first, the result is then already in lowest terms
+        let gcd_1 = checked_gcd(self.numerator.clone(), rhs.denominator.clone())?;
+        let gcd_2 = checked_gcd(rhs.numerator.clone(), self.denominator.clone())?;
+        Some(Rational {
+            numerator: self
+                .numerator
+                .checked_div(&gcd_1)?
+                .checked_mul(&rhs.numerator.checked_div(&gcd_2)?)?,
+            denominator: self
+                .denominator
+                .checked_div(&gcd_2)?
+                .checked_mul(&rhs.denominator.checked_div(&gcd_1)?)?,
+        })
+    }
+
+    fn checked_div(&self, rhs: &Self) -> Option<Self> {
+        self.checked_mul(&rhs.checked_recip()?)
+    }
+}
+
+impl<T: RationalInt> From<Rational<T>> for f64 {
+    fn from(value: Rational<T>) -> Self {
+        value.numerator.to_f64() / value.denominator.to_f64()
+    }
+}
+
+impl<T: RationalInt> From<Rational<T>> for f32 {
+    fn from(value: Rational<T>) -> Self {
+        f64::from(value) as f32
+    }
+}
+
+impl<T: RationalInt> From<i32> for Rational<T> {
+    fn from(value: i32) -> Self {
+        Rational::from_int(value)
+    }
+}
+
+impl<T: RationalInt> Default for Rational<T> {
+    fn default() -> Self {
+        Rational::zero()
+    }
+}
+
+impl<T: RationalInt> AddAssign for Rational<T> {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = self.checked_add(&rhs).expect("Rational overflow");
+    }
+}
+
+impl<T: RationalInt> Add for Rational<T> {
+    type Output = Self;
+
+    fn add(mut self, other: Self) -> Self {
+        self += other;
+        self
+    }
+}
+
+impl<T: RationalInt> SubAssign for Rational<T> {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = self.checked_sub(&rhs).expect("Rational overflow");
+    }
+}
+
+impl<T: RationalInt> Sub for Rational<T> {
+    type Output = Self;
+
+    fn sub(mut self, other: Self) -> Self {
+        self -= other;
+        self
+    }
+}
+
+impl<T: RationalInt> MulAssign for Rational<T> {
+    fn mul_assign(&mut self, rhs: Self) {
+        *self = self.checked_mul(&rhs).expect("Rational overflow");
+    }
+}
+
+impl<T: RationalInt> Mul for Rational<T> {
+    type Output = Self;
+
+    fn mul(mut self, other: Self) -> Self {
+        self *= other;
+        self
+    }
+}
+
+impl<T: RationalInt> DivAssign for Rational<T> {
+    fn div_assign(&mut self, rhs: Self) {
+        *self = self
+            .checked_div(&rhs)
+            .expect("Rational overflow or division by zero");
+    }
+}
+
+impl<T: RationalInt> Div for Rational<T> {
+    type Output = Self;
+
+    fn div(mut self, other: Self) -> Self {
+        self /= other;
+        self
+    }
+}
+
+impl<T: RationalInt> Ord for Rational<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        if let (Some(lhs), Some(rhs)) = (
+            self.numerator.checked_mul(&other.denominator),
+            other.numerator.checked_mul(&self.denominator),
+        ) {
+            return lhs.cmp(&rhs);
+        }
+
+        // cross-multiplying overflows, compare the continued fraction expansions instead
+        let (mut a, mut b) = (self.numerator.clone(), self.denominator.clone());
+        let (mut c, mut d) = (other.numerator.clone(), other.denominator.clone());
+        let mut flipped = false;
+        loop {
+            let (int_1, rem_1) = floor_div_rem(&a, &b);
+            let (int_2, rem_2) = floor_div_rem(&c, &d);
+            let ordering = match (rem_1.is_zero(), rem_2.is_zero()) {
+                _ if int_1 != int_2 => int_1.cmp(&int_2),
+                (true, true) => std::cmp::Ordering::Equal,
+                (true, false) => std::cmp::Ordering::Less,
+                (false, true) => std::cmp::Ordering::Greater,
+                (false, false) => {
+                    // rem_1 / b < rem_2 / d exactly when b / rem_1 > d / rem_2
+                    (a, b, c, d) = (b, rem_1, d, rem_2);
+                    flipped = !flipped;
+                    continue;
+                }
+            };
+            return if flipped {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+        }
+    }
+}
+
+impl<T: RationalInt> PartialOrd for Rational<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: RationalInt> ZeroExt for Rational<T> {
+    fn zero() -> Self {
+        Rational {
+            numerator: T::zero(),
+            denominator: T::from(1),
+        }
+    }
+    fn is_zero(&self) -> bool {
+        self.numerator.is_zero()
+    }
+}
+
+impl<T: RationalInt> OneExt for Rational<T> {
+    fn one() -> Self {
+        Rational::from_int(1)
+    }
+}
+
+impl<T: RationalInt> AbsExt for Rational<T> {
+    fn abs(self) -> Self {
+        if self.numerator.is_negative() {
+            self.checked_neg().expect("Rational overflow")
+        } else {
+            self
+        }
+    }
+}
+
+impl<T: RationalInt> NumExt for Rational<T> {}
+
+impl<T: RationalInt> FieldExt for Rational<T> {}
+
+/// Prints `a/b`, or just `a` for integers. The alternate flag prints the value as an `f64`
+/// instead, so `{:#.2}` rounds to two decimals.
+impl<T: RationalInt> Display for Rational<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            let val = f64::from(self.clone());
+            return f64::fmt(&val, f);
+        }
+        let numerator = self.numerator.to_string();
+        let digits = numerator.trim_start_matches('-');
+        if self.denominator == T::from(1) {
+            f.pad_integral(!self.numerator.is_negative(), "", digits)
+        } else {
+            let fraction = format!("{}/{}", digits, self.denominator);
+            f.pad_integral(!self.numerator.is_negative(), "", &fraction)
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ParseRationalError {
+    Invalid,
+    ZeroDenominator,
+    Overflow,
+}
+
+impl Display for ParseRationalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ParseRationalError::Invalid => "invalid rational literal",
+            ParseRationalError::ZeroDenominator => "zero denominator",
+            ParseRationalError::Overflow => "rational literal does not fit its integer type",
+        })
+    }
+}
+
+impl std::error::Error for ParseRationalError {}
+
+/// Accumulates decimal `digits` onto `value`, subtracting them if `negative`.
+fn push_digits<T: RationalInt>(
+    mut value: T,
+    digits: &str,
+    negative: bool,
+) -> Result<T, ParseRationalError> {
+    let ten = T::from(10);
+    for byte in digits.bytes() {
+        if !byte.is_ascii_digit() {
+            return Err(ParseRationalError::Invalid);
+        }
+        let digit = T::from((byte - b'0') as i32);
+        let shifted = value.checked_mul(&ten);
+        value = if negative {
+            shifted.and_then(|v| v.checked_sub(&digit))
+        } else {
+            shifted.and_then(|v| v.checked_add(&digit))
+        }
+        .ok_or(ParseRationalError::Overflow)?;
+    }
+    Ok(value)
+}
+
+/// Parses `a/b`, integers and decimals like `-1.25`, all with an optional sign in front.
+impl<T: RationalInt> std::str::FromStr for Rational<T> {
+    type Err = ParseRationalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, unsigned) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+
+        let (numerator, denominator) = if let Some((int, frac)) = unsigned.split_once('.') {
+            if int.is_empty() && frac.is_empty() {
+                return Err(ParseRationalError::Invalid);
+            }
+            // trailing zeros don't change the value, but would scale both parts by 10 each
+            let frac = frac.trim_end_matches('0');
+            let numerator = push_digits(T::zero(), int, negative)?;
+            let numerator = push_digits(numerator, frac, negative)?;
+            let denominator = push_digits(T::from(1), &"0".repeat(frac.len()), false)?;
+            (numerator, denominator)
+        } else if let Some((numerator, denominator)) = unsigned.split_once('/') {
+            if numerator.is_empty() || denominator.is_empty() {
+                return Err(ParseRationalError::Invalid);
+            }
+            let numerator = push_digits(T::zero(), numerator, negative)?;
+            let denominator = push_digits(T::zero(), denominator, false)?;
+            if denominator.is_zero() {
+                return Err(ParseRationalError::ZeroDenominator);
+            }
+            (numerator, denominator)
+        } else {
+            if unsigned.is_empty() {
+                return Err(ParseRationalError::Invalid);
+            }
+            (push_digits(T::zero(), unsigned, negative)?, T::from(1))
+        };
+        Rational::checked_new(numerator, denominator).ok_or(ParseRationalError::Overflow)
+    }
+}
+
+/// An element of the prime field of integers modulo `P`.
+///
+/// `P` has to be a prime below 2^63 for division to be correct, and moduli outside `2..2^63`
+/// fail to compile. Residues are ordered by their value in `0..P`, which only matters for
+/// choosing pivots.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
+pub struct ModInt<const P: u64> {
+    value: u64,
+}
+
+impl<const P: u64> ModInt<P> {
+    pub fn new(value: u64) -> ModInt<P> {
+        const { assert!(P >= 2 && P < 1 << 63, "ModInt modulus must be in 2..2^63") };
+        ModInt { value: value % P }
+    }
+
+    /// The representative in `0..P`.
+    pub fn value(&self) -> u64 {
+        self.value
+    }
+
+    pub fn pow(self, mut exp: u64) -> ModInt<P> {
+        let mut base = self;
+        let mut result = ModInt::new(1);
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result *= base;
+            }
+            base *= base;
+            exp >>= 1;
+        }
+        result
+    }
+
+    /// The multiplicative inverse by Fermat's little theorem, `None` for zero.
+    pub fn inverse(self) -> Option<ModInt<P>> {
+        if self.value == 0 {
+            None
+        } else {
+            Some(self.pow(P - 2))
+        }
+    }
+}
+
+impl<const P: u64> From<u64> for ModInt<P> {
+    fn from(value: u64) -> Self {
+        ModInt::new(value)
+    }
+}
+
+impl<const P: u64> From<i64> for ModInt<P> {
+    fn from(value: i64) -> Self {
+        const { assert!(P >= 2 && P < 1 << 63, "ModInt modulus must be in 2..2^63") };
+        ModInt {
+            value: (value as i128).rem_euclid(P as i128) as u64,
+        }
+    }
+}
+
+impl<const P: u64> AddAssign for ModInt<P> {
+    fn add_assign(&mut self, rhs: Self) {
+        // both are below 2^63, so the sum can't overflow
+        self.value = (self.value + rhs.value) % P;
+    }
+}
+
+impl<const P: u64> Add for ModInt<P> {
+    type Output = Self;
+
+    fn add(mut self, rhs: Self) -> Self {
+        self += rhs;
+        self
+    }
+}
+
+impl<const P: u64> SubAssign for ModInt<P> {
+    fn sub_assign(&mut self, rhs: Self) {
+        self.value = (self.value + P - rhs.value) % P;
+    }
+}
+
+impl<const P: u64> Sub for ModInt<P> {
+    type Output = Self;
+
+    fn sub(mut self, rhs: Self) -> Self {
+        self -= rhs;
+        self
+    }
+}
+
+impl<const P: u64> MulAssign for ModInt<P> {
+    fn mul_assign(&mut self, rhs: Self) {
+        self.value = (self.value as u128 * rhs.value as u128 % P as u128) as u64;
+    }
+}
+
+impl<const P: u64> Mul for ModInt<P> {
+    type Output = Self;
+
+    fn mul(mut self, rhs: Self) -> Self {
+        self *= rhs;
+        self
+    }
+}
+
+impl<const P: u64> DivAssign for ModInt<P> {
+    fn div_assign(&mut self, rhs: Self) {
+        *self = self.checked_div(&rhs).expect("ModInt division by zero");
+    }
+}
+
+impl<const P: u64> Div for ModInt<P> {
+    type Output = Self;
+
+    fn div(mut self, rhs: Self) -> Self {
+        self /= rhs;
+        self
+    }
+}
+
+impl<const P: u64> ZeroExt for ModInt<P> {
+    fn zero() -> Self {
+        const { assert!(P >= 2 && P < 1 << 63, "ModInt modulus must be in 2..2^63") };
+        ModInt { value: 0 }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.value == 0
+    }
+}
+
+impl<const P: u64> Default for ModInt<P> {
+    fn default() -> Self {
+        ModInt::zero()
+    }
+}
+
+impl<const P: u64> OneExt for ModInt<P> {
+    fn one() -> Self {
+        ModInt::new(1)
+    }
+}
+
+/// Residues have no sign, so this is the identity.
+impl<const P: u64> AbsExt for ModInt<P> {
+    fn abs(self) -> Self {
+        self
+    }
+}
+
+/// Only division by zero fails, field arithmetic can't overflow.
+impl<const P: u64> CheckedExt for ModInt<P> {
+    fn checked_add(&self, rhs: &Self) -> Option<Self> {
+        Some(*self + *rhs)
+    }
+
+    fn checked_sub(&self, rhs: &Self) -> Option<Self> {
+        Some(*self - *rhs)
+    }
+
+    fn checked_mul(&self, rhs: &Self) -> Option<Self> {
+        Some(*self * *rhs)
+    }
+
+    fn checked_div(&self, rhs: &Self) -> Option<Self> {
+        Some(*self * rhs.inverse()?)
+    }
+}
+
+impl<const P: u64> NumExt for ModInt<P> {}
+
+impl<const P: u64> FieldExt for ModInt<P> {}
+
+impl<const P: u64> Display for ModInt<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kdtree_nearest_signed() {
+        let points: Vec<[i64; 2]> = (-10..10).flat_map(|x| [[x, -x], [x * 3, x]]).collect();
+        let kdtree = KDTree::new(&points);
+        let nearest: Vec<&[i64; 2]> = kdtree
+            .iter_nearest([-21, -6])
+            .take(2)
+            .map(|(_, p, _)| p)
+            .collect();
+        assert_eq!(nearest, vec![&[-21, -7], &[-18, -6]]);
+        assert_eq!(kdtree.iter_nearest([0, 0]).count(), points.len());
+    }
+
+    #[test]
+    fn test_kdtree_nearest_float() {
+        let points: Vec<[f64; 3]> = (0..50)
+            .map(|i| [i as f64 * 0.5, -(i as f64) * 0.25, 1.0])
+            .collect();
+        let kdtree = KDTree::new(&points);
+        assert_eq!(
+            kdtree.iter_nearest([3.1, -1.4, 1.0]).next().map(|n| n.1),
+            Some(&[3.0, -1.5, 1.0])
+        );
+    }
+
+    fn test_points(n: u64) -> Vec<Point3d> {
+        // cheap deterministic scatter, with some duplicated coordinates
+        (0..n)
+            .map(|i| [(i * 7919) % 101, (i * 104729) % 53, (i * 31) % 17])
+            .collect()
+    }
+
+    #[test]
+    fn test_kdtree_seeded_is_deterministic() {
+        let points = test_points(500);
+        let tree1 = KDTree::with_seed(&points, 42);
+        let tree2 = KDTree::with_seed(&points, 42);
+        assert_eq!(format!("{:?}", tree1), format!("{:?}", tree2));
+
+        let areas: Vec<Area> = points
+            .windows(2)
+            .map(|w| Area::from_corners([w[0][0], w[0][1]], [w[1][0], w[1][1]]))
+            .collect();
+        assert_eq!(
+            AreaKDTree::with_seed(&areas, 7),
+            AreaKDTree::with_seed(&areas, 7)
+        );
+    }
+
+    #[test]
+    fn test_kdtree_pivot_strategies() {
+        let points = test_points(500);
+        let target = [50, 20, 8];
+        let mut expected: Vec<u128> = points.iter().map(|p| points_dist(p, &target)).collect();
+        expected.sort();
+        for strategy in [
+            PivotStrategy::RandomSample,
+            PivotStrategy::SeededSample(1),
+            PivotStrategy::Median,
+            PivotStrategy::Midpoint,
+        ] {
+            let kdtree = KDTree::with_pivot_strategy(&points, strategy);
+            let dists: Vec<u128> = kdtree
+                .iter_nearest(target)
+                .map(|(_, p, _)| points_dist(p, &target))
+                .collect();
+            assert_eq!(dists, expected, "{:?}", strategy);
+        }
+    }
+
+    #[test]
+    fn test_area_kdtree_pivot_strategies() {
+        let points = test_points(200);
+        let areas: Vec<Area> = points
+            .windows(2)
+            .map(|w| Area::from_corners([w[0][0], w[0][1]], [w[1][0], w[1][1]]))
+            .collect();
+        let query = Area::from_corners([20, 10], [30, 40]);
+        let expected = areas.iter().any(|a| a.overlaps(&query, Overlap::Exclusive));
+        for strategy in [PivotStrategy::Median, PivotStrategy::Midpoint] {
+            let kdtree = AreaKDTree::with_pivot_strategy(&areas, strategy);
+            assert_eq!(
+                kdtree.any_overlapping(&query, Overlap::Exclusive),
+                expected,
+                "{:?}",
+                strategy
+            );
+            assert_eq!(
+                kdtree.count_overlapping(query, Overlap::Exclusive),
+                areas
+                    .iter()
+                    .filter(|a| a.overlaps(&query, Overlap::Exclusive))
+                    .collect::<HashSet<_>>()
+                    .len()
+            );
+        }
+    }
+
+    #[test]
+    fn test_box_operations() {
+        let a: Area = AaBox::from_corners([4, 0], [0, 3]);
+        assert_eq!(a, AaBox::new([0, 0], [4, 3]));
+        assert_eq!(a.volume(), Some(12));
+        assert_eq!(a.lattice_points(), Some(20));
+        assert!(a.contains_point(&[4, 3]) && a.contains_point(&[2, 1]));
+        assert!(!a.contains_point(&[5, 1]));
+        assert!(a.contains(&AaBox::new([1, 1], [4, 2])));
+        assert!(!a.contains(&AaBox::new([1, 1], [5, 2])));
+
+        let b = AaBox::new([4, 3], [8, 9]);
+        assert!(a.overlaps(&b, Overlap::Inclusive));
+        assert!(!a.overlaps(&b, Overlap::Exclusive));
+        assert_eq!(a.intersection(&b), Some(AaBox::new([4, 3], [4, 3])));
+        assert_eq!(a.intersection(&b).unwrap().volume(), Some(0));
+        assert_eq!(a.union(&b), AaBox::new([0, 0], [8, 9]));
+        assert_eq!(a.intersection(&AaBox::new([5, 0], [6, 1])), None);
+
+        let c: AaBox<i64, 3> = AaBox::new([-2, -2, -2], [2, 2, 2]);
+        let d = AaBox::new([1, -5, 0], [7, 0, 1]);
+        assert!(c.overlaps(&d, Overlap::Exclusive));
+        assert_eq!(c.intersection(&d), Some(AaBox::new([1, -2, 0], [2, 0, 1])));
+        assert_eq!(c.volume(), Some(64));
+        assert_eq!(c.lattice_points(), Some(125));
+        let full: AaBox<u64, 3> = AaBox::new([0; 3], [u64::MAX; 3]);
+        assert_eq!(full.volume(), None);
+        assert_eq!(full.lattice_points(), None);
+        let plane: Area = AaBox::new([0; 2], [u64::MAX; 2]);
+        assert_eq!(plane.volume(), Some(u64::MAX as u128 * u64::MAX as u128));
+        assert_eq!(plane.lattice_points(), None);
+
+        let e = AaBox::new([0.5, 0.5], [1.5, 2.5]);
+        assert_eq!(e.volume(), Some(2.0));
+        assert!(e.overlaps(&AaBox::new([1.5, 0.0], [3.0, 1.0]), Overlap::Inclusive));
+        assert!(!e.overlaps(&AaBox::new([1.5, 0.0], [3.0, 1.0]), Overlap::Exclusive));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_box_new_checks_corners() {
+        AaBox::new([0, 5], [3, 4]);
+    }
+
+    #[test]
+    fn test_area_covered_at_least() {
+        let points: Vec<Point2d> = test_points(60)
+            .iter()
+            .map(|p| [p[0] % 40, p[1] % 30])
+            .collect();
+        let areas: Vec<Area> = points
+            .windows(2)
+            .map(|w| Area::from_corners(w[0], w[1]))
+            .chain([Area::new([3, 3], [3, 3]), Area::new([3, 3], [3, 3])])
+            .collect();
+        let mut coverage = [[0; 30]; 40];
+        for area in &areas {
+            for row in &mut coverage[area.min[0] as usize..=area.max[0] as usize] {
+                for count in &mut row[area.min[1] as usize..=area.max[1] as usize] {
+                    *count += 1;
+                }
+            }
+        }
+        for k in 1..6 {
+            let expected = coverage.iter().flatten().filter(|&&c| c >= k).count();
+            assert_eq!(area_covered_at_least(&areas, k), expected as u128, "{}", k);
+        }
+        assert_eq!(union_area(&areas), area_covered_at_least(&areas, 1));
+        assert_eq!(union_area(&[]), 0);
+        assert_eq!(Some(union_area(&areas[..1])), areas[0].lattice_points());
+    }
+
+    #[test]
+    fn test_area_covered_at_least_extremes() {
+        let max = u64::MAX;
+        let areas = [
+            Area::new([0, 0], [max - 1, max]),
+            Area::new([max, max], [max, max]),
+            Area::new([max - 1, 0], [max, 1]),
+        ];
+        assert_eq!(union_area(&areas), max as u128 * (max as u128 + 1) + 1 + 2);
+        assert_eq!(area_covered_at_least(&areas, 2), 2);
+        assert_eq!(area_covered_at_least(&areas, 3), 0);
+    }
+
+    #[test]
+    fn test_area_kdtree_3d() {
+        let boxes: Vec<AaBox<u64, 3>> = test_points(300)
+            .windows(2)
+            .map(|w| AaBox::from_corners(w[0], w[1]))
+            .collect();
+        let kdtree = AreaKDTree::with_seed(&boxes, 5);
+        assert!(kdtree.stats().nodes > 0);
+        for query in [
+            AaBox::new([10, 10, 3], [20, 15, 4]),
+            AaBox::new([50, 0, 0], [50, 52, 16]),
+            AaBox::new([0, 0, 0], [1, 1, 1]),
+        ] {
+            for mode in [Overlap::Inclusive, Overlap::Exclusive] {
+                let expected: HashSet<_> = boxes
+                    .iter()
+                    .filter(|b| b.overlaps(&query, mode))
+                    .copied()
+                    .collect();
+                assert_eq!(kdtree.get_overlapping(query, mode), expected);
+                assert_eq!(kdtree.any_overlapping(&query, mode), !expected.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn test_area_kdtree_overlap_iter() {
+        let points = test_points(400);
+        let mut areas: Vec<Area> = points
+            .windows(3)
+            .map(|w| Area::from_corners([w[0][0], w[1][1]], [w[2][0], w[0][1]]))
+            .collect();
+        // the same area twice is reported twice
+        areas.extend_from_within(..20);
+
+        let sorted = |mut areas: Vec<Area>| {
+            areas.sort_by_key(|a| (a.min, a.max));
+            areas
+        };
+        let queries = [
+            Area::new([20, 10], [30, 40]),
+            Area::new([0, 0], [100, 52]),
+            Area::new([50, 25], [50, 25]),
+            Area::new([90, 0], [95, 3]),
+        ];
+        for strategy in [PivotStrategy::Median, PivotStrategy::Midpoint] {
+            let kdtree = AreaKDTree::with_pivot_strategy(&areas, strategy);
+            assert!(kdtree.stats().duplicated > 0);
+            for query in queries {
+                for mode in [Overlap::Inclusive, Overlap::Exclusive] {
+                    let expected: Vec<Area> = areas
+                        .iter()
+                        .filter(|a| a.overlaps(&query, mode))
+                        .copied()
+                        .collect();
+                    let found: Vec<Area> = kdtree.overlapping(query, mode).copied().collect();
+                    assert_eq!(sorted(found), sorted(expected.clone()));
+                    assert_eq!(kdtree.count_overlapping(query, mode), expected.len());
+                }
+
+                let expected: Vec<Area> = areas
+                    .iter()
+                    .filter(|a| query.contains(a))
+                    .copied()
+                    .collect();
+                assert_eq!(
+                    sorted(kdtree.within(query).copied().collect()),
+                    sorted(expected)
+                );
+
+                let expected: Vec<Area> = areas
+                    .iter()
+                    .filter(|a| a.contains_point(&query.min))
+                    .copied()
+                    .collect();
+                let found = kdtree.containing_point(query.min).copied().collect();
+                assert_eq!(sorted(found), sorted(expected));
+            }
+        }
+    }
+
+    #[test]
+    fn test_kdtree_stats_and_export() {
+        let points = test_points(300);
+        let mut kdtree = KDTree::with_pivot_strategy(&points, PivotStrategy::Median);
+        let stats = kdtree.stats();
+        assert_eq!(stats.nodes + 1, stats.leaves);
+        assert_eq!(
+            stats
+                .leaf_sizes
+                .iter()
+                .map(|(size, n)| size * n)
+                .sum::<usize>(),
+            points.len()
+        );
+        assert_eq!(stats.leaf_sizes.values().sum::<usize>(), stats.leaves);
+        assert_eq!(stats.duplicated, 0);
+        assert!(stats.balance_factor >= 0.5 && stats.balance_factor <= 1.0);
+        assert!(stats.depth >= 6 && stats.depth < 20, "{:?}", stats);
+
+        // rebuilds leave unused nodes behind, which must not show up in the exports
+        for point in &points[..100] {
+            kdtree.remove(point);
+        }
+        let stats = kdtree.stats();
+        let dot = kdtree.to_dot();
+        assert!(dot.starts_with("digraph kdtree {"));
+        assert_eq!(dot.matches(" -> ").count(), 2 * stats.nodes);
+        assert_eq!(dot.matches("shape=box").count(), stats.leaves);
+
+        let json: JsonValue = kdtree.to_json().stringify().unwrap().parse().unwrap();
+        let mut indices = Vec::new();
+        let mut stack = vec![&json];
+        while let Some(node) = stack.pop() {
+            let node: &HashMap<String, JsonValue> = node.get().unwrap();
+            if let Some(points) = node.get("points").and_then(|p| p.get::<Vec<JsonValue>>()) {
+                indices.extend(
+                    points
+                        .iter()
+                        .map(|p| *p["index"].get::<f64>().unwrap() as usize),
+                );
+            } else {
+                stack.extend([&node["left"], &node["right"]]);
+            }
+        }
+        indices.sort();
+        assert_eq!(indices, (100..300).collect::<Vec<_>>());
+
+        // coordinates beyond 2^53 don't fit an f64, so they are exported as strings
+        let big = u64::MAX - 1;
+        let json = KDTree::new(&[[big, 1]]).to_json();
+        let point = &json["points"][0]["point"];
+        assert_eq!(point[0].get::<String>().unwrap().parse::<u64>(), Ok(big));
+        assert_eq!(point[1].get::<f64>(), Some(&1.0));
+    }
+
+    #[test]
+    fn test_area_kdtree_stats_and_export() {
+        let areas = [
+            Area::from_corners([0, 0], [2, 2]),
+            Area::from_corners([0, 4], [2, 6]),
+            Area::from_corners([4, 0], [6, 2]),
+            Area::from_corners([4, 4], [6, 6]),
+        ]
+        .repeat(3)
+        .into_iter()
+        .chain([Area::from_corners([1, 1], [5, 5])])
+        .collect::<Vec<_>>();
+        let kdtree = AreaKDTree::with_pivot_strategy(&areas, PivotStrategy::Median);
+        assert_eq!(kdtree.len(), 13);
+
+        let stats = kdtree.stats();
+        let stored: usize = stats.leaf_sizes.iter().map(|(size, n)| size * n).sum();
+        assert!(stats.duplicated > 0);
+        assert_eq!(stored, areas.len() + stats.duplicated);
+        assert_eq!(stats.nodes + 1, stats.leaves);
+
+        let dot = kdtree.to_dot();
+        assert_eq!(dot.matches(" -> ").count(), 2 * stats.nodes);
+        let json = kdtree.to_json().stringify().unwrap();
+        assert_eq!(json.matches("min").count(), stored);
+    }
+
+    #[test]
+    fn test_kdtree_bounded_queries() {
+        let points = test_points(500);
+        let kdtree = KDTree::with_seed(&points, 3);
+        let target = [40, 30, 3];
+        let mut expected: Vec<u128> = points.iter().map(|p| points_dist(p, &target)).collect();
+        expected.sort();
+
+        let k_nearest = kdtree.k_nearest(target, 10);
+        assert_eq!(
+            k_nearest.iter().map(|&(_, _, d)| d).collect::<Vec<_>>(),
+            expected[..10]
+        );
+        assert!(
+            k_nearest
+                .iter()
+                .all(|&(i, p, d)| *p == points[i] && points_dist(p, &target) == d)
+        );
+        assert_eq!(kdtree.k_nearest(target, 0), vec![]);
+        assert_eq!(kdtree.k_nearest(target, 1000).len(), points.len());
+
+        let within = kdtree.within_radius(target, 100);
+        assert_eq!(
+            within.iter().map(|&(_, _, d)| d).collect::<Vec<_>>(),
+            expected
+                .iter()
+                .copied()
+                .filter(|&d| d <= 100)
+                .collect::<Vec<_>>()
+        );
+
+        let query = points[17];
+        let (_, nearest, dist) = kdtree.nearest_excluding(query).unwrap();
+        assert_ne!(*nearest, query);
+        assert_eq!(
+            Some(dist),
+            points
+                .iter()
+                .filter(|&&p| p != query)
+                .map(|p| points_dist(p, &query))
+                .min()
+        );
+    }
+
+    #[test]
+    fn test_kdtree_insert_remove() {
+        let points = test_points(300);
+        let mut kdtree = KDTree::with_seed(&points[..20], 5);
+        let mut expected: Vec<Point3d> = points[..20].to_vec();
+        let target = [60, 25, 9];
+        for (i, &point) in points.iter().enumerate().skip(20) {
+            assert_eq!(kdtree.insert(point), i);
+            expected.push(point);
+            if i % 3 == 0 {
+                let removed = expected.swap_remove(i % expected.len());
+                let index = kdtree.remove(&removed).unwrap();
+                assert_eq!(points[index], removed);
+            }
+            assert_eq!(kdtree.len(), expected.len());
+            // slots given up by moved and rebuilt leaves are reclaimed
+            assert!(2 * kdtree.unused_slots <= kdtree.leaf_points.len());
+            let mut dists: Vec<u128> = expected.iter().map(|p| points_dist(p, &target)).collect();
+            dists.sort();
+            dists.truncate(5);
+            let nearest: Vec<u128> = kdtree.k_nearest(target, 5).iter().map(|n| n.2).collect();
+            assert_eq!(nearest, dists);
+        }
+        assert_eq!(kdtree.remove(&[1000, 1000, 1000]), None);
+
+        while let Some(point) = expected.pop() {
+            assert!(kdtree.remove(&point).is_some());
+        }
+        assert!(kdtree.is_empty());
+        assert_eq!(kdtree.iter_nearest(target).next(), None);
+    }
+
+    #[test]
+    fn test_kdtree_insert_duplicates() {
+        let points = test_points(50);
+        let mut kdtree = KDTree::with_seed(&points, 4);
+        // a median rebuild puts every copy on the same side, so this used to rebuild on every
+        // insert
+        let duplicate = [7, 7, 7];
+        for i in 0..20000 {
+            assert_eq!(kdtree.insert(duplicate), points.len() + i);
+        }
+        assert_eq!(kdtree.len(), points.len() + 20000);
+        let box_around = AaBox {
+            min: duplicate,
+            max: duplicate,
+        };
+        let copies = points.iter().filter(|&&p| p == duplicate).count() + 20000;
+        assert_eq!(kdtree.count_within_box(box_around), copies);
+        let nearest = kdtree.k_nearest([100, 50, 20], 3);
+        let mut expected: Vec<u128> = points
+            .iter()
+            .map(|p| points_dist(p, &[100, 50, 20]))
+            .collect();
+        expected.extend([points_dist(&duplicate, &[100, 50, 20]); 3]);
+        expected.sort();
+        assert_eq!(
+            nearest.iter().map(|n| n.2).collect::<Vec<_>>(),
+            expected[..3]
+        );
+        for _ in 0..20000 {
+            assert!(kdtree.remove(&duplicate).is_some());
+        }
+        assert_eq!(kdtree.len(), points.len());
+    }
+
+    #[test]
+    fn test_kdtree_metrics() {
+        let points: Vec<[i64; 2]> = test_points(400)
+            .iter()
+            .map(|&[x, y, _]| [x as i64 - 50, y as i64 - 25])
+            .collect();
+        let kdtree = KDTree::with_seed(&points, 11);
+        let target = [3, -4];
+
+        let mut expected: Vec<u128> = points.iter().map(|p| Manhattan.dist(p, &target)).collect();
+        expected.sort();
+        let dists: Vec<u128> = kdtree
+            .iter_nearest_by(target, Manhattan)
+            .map(|(_, p, _)| Manhattan.dist(p, &target))
+            .collect();
+        assert_eq!(dists, expected);
+        let within = kdtree.within_radius_by(target, 6, Manhattan);
+        assert_eq!(within.len(), expected.iter().filter(|&&d| d <= 6).count());
+
+        let mut expected: Vec<u128> = points.iter().map(|p| Chebyshev.dist(p, &target)).collect();
+        expected.sort();
+        let dists: Vec<u128> = kdtree
+            .k_nearest_by(target, 20, Chebyshev)
+            .iter()
+            .map(|n| n.2)
+            .collect();
+        assert_eq!(dists, expected[..20]);
+        assert_eq!(Chebyshev.dist(&[1.5, -2.0], &[0.0, 1.0]), 3.0);
+    }
+
+    #[test]
+    fn test_kdtree_shared_between_threads() {
+        fn assert_send_sync<S: Send + Sync>(_: &S) {}
+
+        let points = test_points(500);
+        let kdtree = KDTree::with_seed(&points, 9);
+        let area_kdtree = AreaKDTree::new(&[Area::from_corners([0, 0], [1, 1])]);
+        assert_send_sync(&kdtree);
+        assert_send_sync(&area_kdtree);
+
+        let nearest: Vec<Option<Neighbour<u64, 3>>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = points[..4]
+                .iter()
+                .map(|&point| {
+                    let kdtree = &kdtree;
+                    scope.spawn(move || kdtree.nearest_excluding(point))
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        for (point, nearest) in points.iter().zip(nearest) {
+            assert_eq!(nearest, kdtree.nearest_excluding(*point));
+        }
+    }
+
+    #[test]
+    fn test_kdtree_duplicate_points() {
+        let points: [[u64; 2]; 5] = [[1, 1], [5, 5], [1, 1], [2, 2], [1, 1]];
+        let kdtree = KDTree::new(&points);
+        let mut indices: Vec<usize> = kdtree
+            .iter_nearest([1, 1])
+            .take_while(|&(_, _, dist)| dist == 0)
+            .map(|(i, _, _)| i)
+            .collect();
+        indices.sort();
+        assert_eq!(indices, vec![0, 2, 4]);
+
+        let (i, point, dist) = kdtree.iter_nearest([1, 1]).find(|n| n.0 != 0).unwrap();
+        assert!(i == 2 || i == 4);
+        assert_eq!((*point, dist), ([1, 1], 0));
+    }
+
+    #[test]
+    fn test_kdtree_index_based_removal() {
+        let points: [[u64; 2]; 5] = [[1, 1], [5, 5], [1, 1], [2, 2], [1, 1]];
+        let mut kdtree = KDTree::new(&points);
+        // excluding by coordinates skips every copy, excluding by index only the point itself
+        assert_eq!(kdtree.nearest_excluding([1, 1]).unwrap().0, 3);
+        let (i, point, dist) = kdtree.nearest_excluding_index(0).unwrap();
+        assert!(i == 2 || i == 4);
+        assert_eq!((*point, dist), ([1, 1], 0));
+
+        assert_eq!(kdtree.remove_index(2), Some([1, 1]));
+        assert_eq!(kdtree.remove_index(2), None);
+        assert_eq!(kdtree.get(2), None);
+        assert_eq!(kdtree.nearest_excluding_index(0).unwrap().0, 4);
+        assert_eq!(kdtree.nearest_excluding_index(2), None);
+        assert_eq!(kdtree.remove_index(4), Some([1, 1]));
+        assert_eq!(kdtree.nearest_excluding_index(0).unwrap().0, 3);
+        assert_eq!(kdtree.remove_index(17), None);
+
+        let index = kdtree.insert([1, 1]);
+        assert_eq!(index, 5);
+        assert_eq!(kdtree.get(index), Some(&[1, 1]));
+        assert_eq!(kdtree.remove(&[1, 1]).map(|i| kdtree.get(i)), Some(None));
+        assert_eq!(kdtree.len(), 3);
+    }
+
+    #[test]
+    fn test_points_dist_no_overflow() {
+        let dist = points_dist(&[0u64, 0, 0], &[u64::MAX, 0, 0]);
+        assert_eq!(dist, (u64::MAX as u128).pow(2));
+        let dist = points_dist(&[0u64, 1 << 50, 0], &[1 << 50, 0, 1 << 50]);
+        assert_eq!(dist, 3 << 100);
+        assert_eq!(
+            points_dist(&[i32::MIN], &[i32::MAX]),
+            (u32::MAX as u128).pow(2)
+        );
+
+        // two full-range axes don't fit, so the sum saturates instead of wrapping
+        assert_eq!(points_dist(&[0u64, 0], &[u64::MAX, u64::MAX]), u128::MAX);
+        assert_eq!(
+            Manhattan.dist(&[0u64, 0, 0], &[u64::MAX, u64::MAX, 1]),
+            2 * u64::MAX as u128 + 1
+        );
+        let kdtree = KDTree::new(&[[u64::MAX, u64::MAX], [0, 0], [1 << 40, 1]]);
+        let nearest: Vec<usize> = kdtree.k_nearest([0, 0], 3).iter().map(|n| n.0).collect();
+        assert_eq!(nearest, vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn test_kdtree_box_queries() {
+        let points = test_points(1000);
+        let mut kdtree = KDTree::new(&points);
+        kdtree.remove(&points[3]);
+        let boxes = [
+            ([10, 5, 0], [60, 30, 8]),
+            ([0, 0, 0], [100, 52, 16]),
+            ([50, 20, 3], [50, 40, 3]),
+            ([40, 40, 10], [30, 50, 12]),
+            ([101, 0, 0], [200, 100, 100]),
+        ];
+        for (min, max) in boxes {
+            // some of the boxes are empty, so skip the corner check in `AaBox::new`
+            let bounds = AaBox { min, max };
+            let mut expected: Vec<usize> = (0..points.len())
+                .filter(|&i| i != 3 && bounds.contains_point(&points[i]))
+                .collect();
+            let mut found: Vec<usize> = kdtree.within_box(bounds).map(|(i, _)| i).collect();
+            found.sort();
+            expected.sort();
+            assert_eq!(found, expected);
+            assert_eq!(kdtree.count_within_box(bounds), expected.len());
+        }
+
+        let points: [[f64; 2]; 4] = [[-1.5, 0.0], [0.25, 0.5], [2.0, -3.0], [0.0, 0.0]];
+        let kdtree = KDTree::new(&points);
+        let mut found: Vec<usize> = kdtree
+            .within_box(AaBox::new([-1.5, -1.0], [0.25, 0.5]))
+            .map(|(i, _)| i)
+            .collect();
+        found.sort();
+        assert_eq!(found, vec![0, 1, 3]);
+        assert_eq!(
+            kdtree.count_within_box(AaBox::new([-1.0, -1.0], [1.0, 1.0])),
+            2
+        );
+    }
+
+    #[test]
+    fn test_kdtree_closest_pairs() {
+        let points = test_points(200);
+        let mut kdtree = KDTree::new(&points);
+        kdtree.remove(&points[7]);
+
+        let mut iter_indices: Vec<usize> = kdtree.iter().map(|(i, _)| i).collect();
+        iter_indices.sort();
+        assert_eq!(
+            iter_indices,
+            (0..200).filter(|&i| i != 7).collect::<Vec<_>>()
+        );
+
+        let pairs: Vec<(usize, usize, u128)> = kdtree.closest_pairs().collect();
+        let mut expected = Vec::new();
+        for i in 0..points.len() {
+            for j in i + 1..points.len() {
+                if i != 7 && j != 7 {
+                    expected.push(points_dist(&points[i], &points[j]));
+                }
+            }
+        }
+        expected.sort();
+        assert_eq!(pairs.len(), expected.len());
+        assert!(
+            pairs
+                .iter()
+                .all(|&(i, j, dist)| i < j && dist == points_dist(&points[i], &points[j]))
+        );
+        assert_eq!(pairs.iter().map(|p| p.2).collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_kdtree_minimum_spanning_tree() {
+        let points = test_points(150);
+        let kdtree = KDTree::new(&points);
+        let mst = kdtree.minimum_spanning_tree();
+        assert_eq!(mst.len(), points.len() - 1);
+
+        // Prim's algorithm on the complete graph gives the same total weight
+        let mut in_tree = vec![false; points.len()];
+        let mut best = vec![u128::MAX; points.len()];
+        best[0] = 0;
+        let mut total = 0;
+        for _ in 0..points.len() {
+            let next = (0..points.len())
+                .filter(|&i| !in_tree[i])
+                .min_by_key(|&i| best[i])
+                .unwrap();
+            in_tree[next] = true;
+            total += best[next];
+            for i in 0..points.len() {
+                best[i] = best[i].min(points_dist(&points[next], &points[i]));
+            }
+        }
+        assert_eq!(mst.iter().map(|e| e.2).sum::<u128>(), total);
+
+        let mut sets = DisjointSets::new(points.len());
+        assert!(mst.iter().all(|&(i, j, _)| sets.union(i, j)));
+        assert_eq!(sets.set_size(0), points.len());
+    }
+
+    #[test]
+    fn test_kdtree_clusters() {
+        let points: [[i64; 2]; 7] = [
+            [0, 0],
+            [0, 1],
+            [10, 10],
+            [10, 11],
+            [11, 11],
+            [50, 50],
+            [0, 3],
+        ];
+        let kdtree = KDTree::new(&points);
+
+        let sorted = |mut clusters: Vec<Vec<usize>>| {
+            clusters.iter_mut().for_each(|c| c.sort());
+            clusters.sort();
+            clusters
+        };
+        assert_eq!(kdtree.clusters_after_merges(0).len(), 7);
+        assert_eq!(
+            sorted(kdtree.clusters_after_merges(4)),
+            vec![vec![0, 1, 6], vec![2, 3, 4], vec![5]]
+        );
+        // the fourth closest pair, 2 and 4, is already connected and doesn't merge anything
+        assert_eq!(
+            sorted(kdtree.clusters_after_pairs(4)),
+            vec![vec![0, 1], vec![2, 3, 4], vec![5], vec![6]]
+        );
+        assert_eq!(
+            sorted(kdtree.clusters_after_merges(6)),
+            vec![(0..7).collect::<Vec<_>>()]
+        );
     }
-}
 
-impl Add for Rational {
-    type Output = Self;
+    #[test]
+    fn test_rectilinear_polygon_measures() {
+        let polygon = RectilinearPolygon::new(vec![
+            [7u64, 1],
+            [11, 1],
+            [11, 7],
+            [9, 7],
+            [9, 5],
+            [2, 5],
+            [2, 3],
+            [7, 3],
+        ])
+        .unwrap();
+        assert_eq!(polygon.area(), Some(30));
+        assert_eq!(polygon.boundary_points(), 30);
+        assert_eq!(polygon.interior_points(), Some(16));
+        let brute_force = (0..13)
+            .flat_map(|x| (0..9).map(move |y| [x, y]))
+            .filter(|&p| polygon.contains_point(p))
+            .count();
+        assert_eq!(polygon.lattice_points(), Some(brute_force as u128));
+        let whole_plane = AaBox::new([0, 0], [u64::MAX, u64::MAX]);
+        assert_eq!(
+            polygon.prefix_sum().count_filled(&whole_plane),
+            brute_force as u128
+        );
+        assert_eq!(polygon.locate([7, 1]), PointLocation::OnBoundary);
+        assert_eq!(polygon.locate([8, 4]), PointLocation::Inside);
+        assert_eq!(polygon.locate([3, 2]), PointLocation::Outside);
+        assert_eq!(polygon.locate([10, 8]), PointLocation::Outside);
 
-    fn add(mut self, other: Self) -> Self {
-        self += other;
-        self
-    }
-}
+        // orientation doesn't matter
+        let mut reversed = polygon.vertices().to_vec();
+        reversed.reverse();
+        let reversed = RectilinearPolygon::new(reversed).unwrap();
+        assert_eq!(reversed.area(), Some(30));
 
-impl SubAssign for Rational {
-    fn sub_assign(&mut self, rhs: Self) {
-        self.numerator = self.numerator * rhs.denominator - rhs.numerator * self.denominator;
-        self.denominator *= rhs.denominator;
-        self.normalize();
+        // a square of side 2^63 far from the origin still fits, the full range doesn't
+        let (low, side) = (u64::MAX - (1 << 63), 1u64 << 63);
+        let huge = RectilinearPolygon::new(vec![
+            [low, low],
+            [low + side, low],
+            [low + side, low + side],
+            [low, low + side],
+        ])
+        .unwrap();
+        assert_eq!(huge.area(), Some(1 << 126));
+        assert_eq!(huge.boundary_points(), 1 << 65);
+        assert_eq!(huge.interior_points(), Some((1 << 126) - (1 << 64) + 1));
+        assert_eq!(huge.lattice_points(), Some((1 << 126) + (1 << 64) + 1));
+        let full = RectilinearPolygon::new(vec![
+            [0, 0],
+            [u64::MAX, 0],
+            [u64::MAX, u64::MAX],
+            [0, u64::MAX],
+        ])
+        .unwrap();
+        assert_eq!(full.area(), None);
+        assert_eq!(full.lattice_points(), None);
+
+        assert!(RectilinearPolygon::new(vec![[0i64, 0], [2, 0], [2, 2]]).is_none());
+        assert!(RectilinearPolygon::new(vec![[0i64, 0], [2, 0], [2, 2], [1, 3]]).is_none());
+        assert!(RectilinearPolygon::new(vec![[0i64, 0], [2, 0], [2, 2], [2, 2], [0, 2]]).is_none());
     }
-}
 
-impl Sub for Rational {
-    type Output = Self;
+    #[test]
+    fn test_rectilinear_polygon_contains_box() {
+        // a U shape, with a notch cut into its top
+        let polygon = RectilinearPolygon::new(vec![
+            [0i64, 0],
+            [10, 0],
+            [10, 10],
+            [6, 10],
+            [6, 4],
+            [4, 4],
+            [4, 10],
+            [0, 10],
+        ])
+        .unwrap();
+        assert_eq!(polygon.area(), Some(88));
+        assert!(polygon.contains_box(&AaBox::new([0, 0], [10, 4])));
+        assert!(polygon.contains_box(&AaBox::new([0, 4], [4, 10])));
+        assert!(!polygon.contains_box(&AaBox::new([0, 4], [6, 10])));
+        assert!(polygon.contains_box(&AaBox::new([4, 4], [6, 4])));
+        assert!(!polygon.contains_box(&AaBox::new([4, 5], [6, 5])));
+        assert!(polygon.contains_box(&AaBox::new([4, 4], [4, 10])));
 
-    fn sub(mut self, other: Self) -> Self {
-        self -= other;
-        self
+        // every box is inside if all points on a grid twice as fine are inside
+        let filled = polygon.prefix_sum();
+        for x1 in -1..=11 {
+            for x2 in x1..=11 {
+                for y1 in -1..=11 {
+                    for y2 in y1..=11 {
+                        let area = AaBox::new([x1, y1], [x2, y2]);
+                        let expected = (2 * x1..=2 * x2).all(|x| {
+                            (2 * y1..=2 * y2).all(|y| {
+                                polygon.locate_doubled([x as i128, y as i128])
+                                    != PointLocation::Outside
+                            })
+                        });
+                        assert_eq!(polygon.contains_box(&area), expected, "{:?}", area);
+                        assert_eq!(filled.is_filled(&area), expected, "{:?}", area);
+                    }
+                }
+            }
+        }
     }
-}
 
-impl MulAssign for Rational {
-    fn mul_assign(&mut self, rhs: Self) {
-        self.numerator *= rhs.numerator;
-        self.denominator *= rhs.denominator;
-        self.normalize();
+    #[test]
+    fn test_range_set_insert_remove() {
+        let mut set = RangeSet::new();
+        set.insert(10..=14);
+        set.insert(3..5);
+        set.insert(16..20);
+        set.insert(5..=5);
+        assert_eq!(
+            set.iter().collect::<Vec<_>>(),
+            vec![3..=5, 10..=14, 16..=19]
+        );
+        // touching ranges merge
+        set.insert(15..16);
+        set.insert(7..7);
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![3..=5, 10..=19]);
+        assert_eq!(set.len(), 13);
+        assert!(set.contains(3) && set.contains(19) && !set.contains(6) && !set.contains(20));
+
+        set.remove(12..=13);
+        set.remove(..4);
+        assert_eq!(
+            set.iter().collect::<Vec<_>>(),
+            vec![4..=5, 10..=11, 14..=19]
+        );
+        set.remove(5..15);
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![4..=4, 15..=19]);
+        set.remove(..);
+        assert!(set.is_empty());
+        assert_eq!(set.len(), 0);
     }
-}
 
-impl Mul for Rational {
-    type Output = Self;
+    #[test]
+    fn test_range_set_operations() {
+        let a: RangeSet<i32> = [-10..-5, 0..10, 20..30].into_iter().collect();
+        let b: RangeSet<i32> = [-7..=2, 8..=25].into_iter().collect();
+        assert_eq!(a.union(&b).iter().collect::<Vec<_>>(), vec![-10..=29]);
+        assert_eq!(
+            a.intersection(&b).iter().collect::<Vec<_>>(),
+            vec![-7..=-6, 0..=2, 8..=9, 20..=25]
+        );
+        assert_eq!(
+            a.difference(&b).iter().collect::<Vec<_>>(),
+            vec![-10..=-8, 3..=7, 26..=29]
+        );
+        assert_eq!(
+            a.complement(-20..=40).iter().collect::<Vec<_>>(),
+            vec![-20..=-11, -5..=-1, 10..=19, 30..=40]
+        );
+        assert_eq!(a.complement(..).len(), (1 << 32) - a.len());
+        assert_eq!(a.num_ranges(), 3);
 
-    fn mul(mut self, other: Self) -> Self {
-        self *= other;
-        self
+        // brute force over a small domain
+        let contains_all = |set: &RangeSet<i32>, f: &dyn Fn(i32) -> bool| {
+            (-15..35).all(|x| set.contains(x) == f(x))
+        };
+        assert!(contains_all(&a.union(&b), &|x| a.contains(x) || b.contains(x)));
+        assert!(contains_all(&a.intersection(&b), &|x| a.contains(x) && b.contains(x)));
+        assert!(contains_all(&a.difference(&b), &|x| a.contains(x) && !b.contains(x)));
     }
-}
 
-impl DivAssign for Rational {
-    fn div_assign(&mut self, rhs: Self) {
-        self.numerator *= rhs.denominator;
-        self.denominator *= rhs.numerator;
-        self.normalize();
-    }
-}
+    #[test]
+    fn test_range_set_extremes() {
+        let mut set = RangeSet::new();
+        set.insert(u64::MAX - 2..=u64::MAX);
+        set.insert(0..1);
+        set.insert(u64::MAX - 5..u64::MAX - 3);
+        assert_eq!(
+            set.iter().collect::<Vec<_>>(),
+            vec![0..=0, u64::MAX - 5..=u64::MAX - 4, u64::MAX - 2..=u64::MAX]
+        );
+        assert!(set.contains(u64::MAX));
+        set.insert(u64::MAX - 3..=u64::MAX - 3);
+        assert_eq!(set.num_ranges(), 2);
+        set.remove(u64::MAX..);
+        assert_eq!(set.iter().last(), Some(u64::MAX - 5..=u64::MAX - 1));
 
-impl Div for Rational {
-    type Output = Self;
+        let full: RangeSet<u64> = [..].into_iter().collect();
+        assert_eq!(full.len(), 1 << 64);
+        assert_eq!(full.complement(..), RangeSet::new());
 
-    fn div(mut self, other: Self) -> Self {
-        self /= other;
-        self
+        let mut set: RangeSet<i64> = [i64::MIN..=i64::MIN + 1, 5..=i64::MAX]
+            .into_iter()
+            .collect();
+        set.remove(i64::MIN..=i64::MIN);
+        set.insert(0..0);
+        set.insert(i64::MAX..i64::MAX);
+        assert_eq!(
+            set.iter().collect::<Vec<_>>(),
+            vec![i64::MIN + 1..=i64::MIN + 1, 5..=i64::MAX]
+        );
+        assert_eq!(
+            set.complement(..).iter().collect::<Vec<_>>(),
+            vec![i64::MIN..=i64::MIN, i64::MIN + 2..=4]
+        );
     }
-}
 
-impl Ord for Rational {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        (self.numerator * other.denominator).cmp(&(self.denominator * other.numerator))
-    }
-}
+    #[test]
+    fn test_coordinate_compression() {
+        let compression = CoordinateCompression::new([7u64, 3, 4, 10, 7, u64::MAX]);
+        let cells: Vec<_> = (0..compression.num_cells())
+            .map(|i| compression.cell(i))
+            .collect();
+        assert_eq!(
+            cells,
+            vec![
+                3..=3,
+                4..=4,
+                5..=6,
+                7..=7,
+                8..=9,
+                10..=10,
+                11..=u64::MAX - 1,
+                u64::MAX..=u64::MAX
+            ]
+        );
+        assert_eq!(compression.cell_len(2), 2);
+        assert_eq!(compression.index(6), Some(2));
+        assert_eq!(compression.index(u64::MAX), Some(7));
+        assert_eq!(compression.index(2), None);
 
-impl PartialOrd for Rational {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
+        let grid =
+            GridCompression::from_boxes(&[Area::new([2, 10], [5, 20]), Area::new([3, 0], [9, 10])]);
+        assert_eq!(grid.point([4, 10]), Some([2, 2]));
+        assert_eq!(grid.point([4, 21]), None);
+        assert_eq!(
+            grid.area(&Area::new([3, 5], [9, 20])),
+            Some(AaBox {
+                min: [1, 1],
+                max: [5, 4]
+            })
+        );
     }
-}
 
-impl ZeroExt for Rational {
-    fn zero() -> Self {
-        Rational {
-            numerator: 0,
-            denominator: 1,
-        }
-    }
-    fn is_zero(&self) -> bool {
-        self.numerator == 0
-    }
-}
+    #[test]
+    fn test_prefix_sum_2d() {
+        let points: Vec<Point2d> = test_points(30).iter().map(|p| [p[0], p[1]]).collect();
+        let grid = GridCompression::from_points(&points);
+        let filled_cell = |[i, j]: [usize; 2]| (i * 7 + j * 3) % 5 < 2;
+        let prefix_sum = PrefixSum2D::new(grid.clone(), filled_cell);
+        let filled = |p: Point2d| grid.point(p).is_some_and(filled_cell);
 
-impl AbsExt for Rational {
-    fn abs(self) -> Self {
-        Rational {
-            numerator: self.numerator.abs(),
-            denominator: self.denominator,
+        let queries = [
+            Area::new([0, 0], [120, 60]),
+            Area::new([10, 5], [40, 30]),
+            Area::new([33, 17], [33, 17]),
+            Area::new([50, 0], [53, 2]),
+            Area::new([101, 53], [200, 100]),
+        ];
+        for query in queries
+            .into_iter()
+            .chain(points.windows(2).map(|w| Area::from_corners(w[0], w[1])))
+        {
+            let count = (query.min[0]..=query.max[0])
+                .flat_map(|x| (query.min[1]..=query.max[1]).map(move |y| [x, y]))
+                .filter(|&p| filled(p))
+                .count();
+            assert_eq!(
+                prefix_sum.count_filled(&query),
+                count as u128,
+                "{:?}",
+                query
+            );
+            assert_eq!(
+                prefix_sum.is_filled(&query),
+                Some(count as u128) == query.lattice_points()
+            );
         }
-    }
-}
-
-impl NumExt for Rational {}
 
-impl Display for Rational {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let val = self.numerator as f64 / self.denominator as f64;
-        f64::fmt(&val, f)
+        // largest rectangle between two vertices of the day 9 example that's inside the polygon
+        let vertices = vec![
+            [7u64, 1],
+            [11, 1],
+            [11, 7],
+            [9, 7],
+            [9, 5],
+            [2, 5],
+            [2, 3],
+            [7, 3],
+        ];
+        let polygon = RectilinearPolygon::new(vertices.clone()).unwrap();
+        let grid = GridCompression::from_points(&vertices);
+        let cell_start =
+            |[i, j]: [usize; 2]| [*grid.axes[0].cell(i).start(), *grid.axes[1].cell(j).start()];
+        let prefix_sum = PrefixSum2D::new(grid.clone(), |cell| {
+            polygon.contains_point(cell_start(cell))
+        });
+        let largest = vertices
+            .iter()
+            .flat_map(|&v1| vertices.iter().map(move |&v2| Area::from_corners(v1, v2)))
+            .filter(|area| prefix_sum.is_filled(area))
+            .map(|area| area.lattice_points().unwrap())
+            .max();
+        assert_eq!(largest, Some(24));
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
     #[test]
     fn test_mat_to_rref_1() {
-        let mut mat = Mat::from_array(&[
+        let mut mat: Mat<Rational> = Mat::from_array(&[
             [Rational::from_int(-1), Rational::from_int(1)],
             [Rational::from_int(-1), Rational::from_int(0)],
             [Rational::from_int(0), Rational::from_int(-1)],
             [Rational::from_int(-1), Rational::from_int(-2)],
         ]);
-        mat.to_rref(false);
+        mat.to_rref(false).unwrap();
 
         let expected = Mat::from_array(&[
             [Rational::from_int(1), Rational::from_int(0)],
@@ -856,12 +6630,12 @@ mod tests {
 
     #[test]
     fn test_mat_to_rref_2() {
-        let mut mat = Mat::from_array(&[
+        let mut mat: Mat<Rational> = Mat::from_array(&[
             [Rational::from_int(-1), Rational::from_int(1)],
             [Rational::from_int(-1), Rational::from_int(2)],
             [Rational::from_int(-3), Rational::from_int(2)],
         ]);
-        mat.to_rref(false);
+        mat.to_rref(false).unwrap();
 
         let expected = Mat::from_array(&[
             [Rational::from_int(1), Rational::from_int(0)],
@@ -874,7 +6648,7 @@ mod tests {
 
     #[test]
     fn test_mat_to_rref_3() {
-        let mut mat = Mat::from_array(&[
+        let mut mat: Mat<Rational> = Mat::from_array(&[
             [
                 Rational::from_int(1),
                 Rational::from_int(3),
@@ -888,7 +6662,7 @@ mod tests {
                 Rational::from_int(-4),
             ],
         ]);
-        mat.to_rref(false);
+        mat.to_rref(false).unwrap();
 
         let expected = Mat::from_array(&[
             [
@@ -909,7 +6683,7 @@ mod tests {
 
     #[test]
     fn test_mat_to_rref_augmented_1() {
-        let mut mat = Mat::from_array(&[
+        let mut mat: Mat<Rational> = Mat::from_array(&[
             [
                 Rational::from_int(1),
                 Rational::from_int(1),
@@ -929,7 +6703,7 @@ mod tests {
                 Rational::from_int(9),
             ],
         ]);
-        mat.to_rref(true);
+        mat.to_rref(true).unwrap();
 
         let expected = Mat::from_array(&[
             [
@@ -956,7 +6730,7 @@ mod tests {
 
     #[test]
     fn test_mat_to_rref_augmented_2() {
-        let mut mat = Mat::from_array(&[
+        let mut mat: Mat<Rational> = Mat::from_array(&[
             [
                 Rational::from_int(1),
                 Rational::from_int(1),
@@ -976,7 +6750,7 @@ mod tests {
                 Rational::from_int(1),
             ],
         ]);
-        mat.to_rref(true);
+        mat.to_rref(true).unwrap();
         println!("{:5.2}", mat);
 
         let expected = Mat::from_array(&[
@@ -1001,4 +6775,866 @@ mod tests {
         ]);
         assert!(mat == expected)
     }
+
+    #[test]
+    fn test_bigint_matches_i128() {
+        use rand::Rng;
+        let mut rng = StdRng::seed_from_u64(17);
+        let big = |v: i128| BigInt::from(v);
+        for _ in 0..2000 {
+            let a: i128 = rng.random::<i64>() as i128 * rng.random_range(1..1_000_000);
+            let b: i128 = match rng.random_range(0..3) {
+                0 => rng.random_range(-5..5),
+                1 => rng.random::<i32>() as i128,
+                _ => rng.random::<i64>() as i128,
+            };
+            assert_eq!(big(a).checked_add(&big(b)), Some(big(a + b)));
+            assert_eq!(big(a).checked_sub(&big(b)), Some(big(a - b)));
+            assert_eq!(big(a).cmp(&big(b)), a.cmp(&b));
+            assert_eq!(big(a).to_string(), a.to_string());
+            if b != 0 {
+                assert_eq!(big(a).checked_div(&big(b)), Some(big(a / b)));
+                assert_eq!(big(a).checked_rem(&big(b)), Some(big(a % b)));
+                assert_eq!(big(b).checked_mul(&big(b)), Some(big(b * b)));
+            }
+        }
+
+        // beyond i128: (a * b * c) / c == a * b
+        let a = big(i128::MAX)
+            .checked_mul(&big(-12345678901234567))
+            .unwrap();
+        let c = big(i128::MIN + 1).checked_add(&big(99)).unwrap();
+        let product = a.checked_mul(&c).unwrap();
+        assert_eq!(product.checked_div(&c), Some(a.clone()));
+        assert_eq!(product.checked_rem(&c), Some(BigInt::zero()));
+        assert_eq!(
+            product.checked_add(&big(5)).unwrap().checked_rem(&a),
+            Some(big(5))
+        );
+        assert_eq!(
+            big(1i128 << 100)
+                .checked_mul(&big(1i128 << 100))
+                .unwrap()
+                .to_string(),
+            "1606938044258990275541962092341162602522202993782792835301376"
+        );
+        assert_eq!(big(0).checked_div(&big(0)), None);
+
+        assert_eq!(u64::try_from(big(0)), Ok(0));
+        assert_eq!(u64::try_from(big(u64::MAX as i128)), Ok(u64::MAX));
+        assert_eq!(u64::try_from(big(u64::MAX as i128 + 1)), Err(Overflow));
+        assert_eq!(u64::try_from(big(-1)), Err(Overflow));
+    }
+
+    #[test]
+    fn test_rational_overflow_is_reported() {
+        let big = Rational::<i64>::new(i64::MAX, 3);
+        assert_eq!(big.checked_mul(&big), None);
+        assert_eq!(big.checked_add(&Rational::new(1, 2)), None);
+        assert_eq!(Rational::<i64>::from_int(i64::MIN).checked_neg(), None);
+        assert_eq!(big.checked_div(&Rational::zero()), None);
+        // operands are cancelled before multiplying, so this fits
+        assert_eq!(
+            big.checked_mul(&Rational::new(3, i64::MAX)),
+            Some(Rational::from_int(1))
+        );
+
+        // comparing falls back to continued fractions when cross-multiplying overflows
+        let a = Rational::<i64>::new(i64::MAX - 1, i64::MAX - 2);
+        let b = Rational::<i64>::new(i64::MAX - 2, i64::MAX - 3);
+        assert!(a < b);
+        assert!(a.checked_neg().unwrap() > b.checked_neg().unwrap());
+        assert!(Rational::<i64>::new(i64::MAX, 2) > Rational::new(i64::MAX - 1, 2));
+
+        // the same system overflows i64 but reduces with wider backings
+        let entries: [[i64; 3]; 2] = [[1 << 40, 3, 1], [7, 1 << 41, 2]];
+        let mut mat: Mat<Rational<i64>> =
+            Mat::from_array(&entries.map(|row| row.map(Rational::from_int)));
+        assert_eq!(mat.to_rref(true), Err(Overflow));
+
+        let mut mat: Mat<Rational<i128>> =
+            Mat::from_array(&entries.map(|row| row.map(Rational::from_int)));
+        mat.to_rref(true).unwrap();
+        let mut big_mat: Mat<Rational<BigInt>> =
+            Mat::from_array(&entries.map(|row| row.map(Rational::from_int)));
+        big_mat.to_rref(true).unwrap();
+        for row in 0..2 {
+            for col in 0..3 {
+                let value = &mat[(row, col)];
+                let big_value = &big_mat[(row, col)];
+                assert_eq!(
+                    big_value.numerator().to_string(),
+                    value.numerator().to_string()
+                );
+                assert_eq!(
+                    big_value.denominator().to_string(),
+                    value.denominator().to_string()
+                );
+            }
+        }
+        assert_eq!(big_mat[(0, 0)], Rational::from_int(1));
+        assert!(big_mat[(0, 1)].is_zero());
+    }
+
+    #[test]
+    fn test_rational_canonical_form_and_text() {
+        use std::collections::HashSet;
+
+        assert_eq!(Rational::<i64>::new(2, 4), Rational::new(1, 2));
+        assert_eq!(Rational::<i64>::new(3, -6), Rational::new(-1, 2));
+        assert_eq!(Rational::<i64>::new(0, -5), Rational::zero());
+        assert_eq!(Rational::<i64>::checked_new(1, 0), None);
+        let set: HashSet<Rational> = [
+            Rational::new(2, 4),
+            Rational::new(-3, -6),
+            Rational::from_int(1) / Rational::from_int(2),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(set.len(), 1);
+
+        let parse = |s: &str| s.parse::<Rational>();
+        assert_eq!(parse("6/-4"), Err(ParseRationalError::Invalid));
+        assert_eq!(parse("-6/4"), Ok(Rational::new(-3, 2)));
+        assert_eq!(parse("+42"), Ok(Rational::from_int(42)));
+        assert_eq!(parse("-1.25"), Ok(Rational::new(-5, 4)));
+        assert_eq!(parse(".5"), Ok(Rational::new(1, 2)));
+        assert_eq!(parse("3."), Ok(Rational::from_int(3)));
+        assert_eq!(parse("1.50000000000000000000"), Ok(Rational::new(3, 2)));
+        assert_eq!(parse("-2.000"), Ok(Rational::from_int(-2)));
+        assert_eq!(parse("1/0"), Err(ParseRationalError::ZeroDenominator));
+        for invalid in ["", "-", ".", "1/", "/2", "1.2.3", "1/2.5", "a", "--1"] {
+            assert_eq!(
+                parse(invalid),
+                Err(ParseRationalError::Invalid),
+                "{invalid}"
+            );
+        }
+        assert_eq!(
+            parse("-9223372036854775808"),
+            Ok(Rational::from_int(i64::MIN))
+        );
+        assert_eq!(
+            parse("9223372036854775808"),
+            Err(ParseRationalError::Overflow)
+        );
+        let big: Rational<BigInt> = "-123456789012345678901234567890/20".parse().unwrap();
+        assert_eq!(big.to_string(), "-12345678901234567890123456789/2");
+
+        let third = Rational::<i64>::new(-1, 3);
+        assert_eq!(third.to_string(), "-1/3");
+        assert_eq!(Rational::<i64>::from_int(7).to_string(), "7");
+        assert_eq!(format!("{:>6}|{:<6}|", third, third), "  -1/3|-1/3  |");
+        assert_eq!(format!("{:#.3}", third), "-0.333");
+        for value in ["-7/3", "5", "0", "22/7"] {
+            assert_eq!(parse(value).unwrap().to_string(), value);
+        }
+    }
+
+    #[test]
+    fn test_mat_algebra() {
+        let r = |s: &str| s.parse::<Rational>().unwrap();
+        let a = Mat::from_array(&[
+            [r("2"), r("1"), r("0")],
+            [r("1"), r("3"), r("1")],
+            [r("0"), r("1"), r("4")],
+        ]);
+        let b = Mat::from_array(&[[r("1"), r("-1")], [r("0"), r("2")], [r("1/2"), r("0")]]);
+
+        let product = &a * &b;
+        assert_eq!(
+            product,
+            Mat::from_array(&[[r("2"), r("0")], [r("3/2"), r("5")], [r("2"), r("2")]])
+        );
+        assert_eq!(b.transpose().transpose(), b);
+        assert_eq!(b.transpose().row(1), &[r("-1"), r("2"), r("0")]);
+        assert_eq!(&(&a + &a) - &a, a);
+        assert_eq!(&a * r("2"), &a + &a);
+        assert_eq!(&(&a * r("3")) / r("3"), a);
+        assert_eq!(&a * &Mat::identity(3), a);
+
+        assert_eq!(a.det(), Ok(r("18")));
+        let inverse = a.inverse().unwrap().unwrap();
+        assert_eq!(&a * &inverse, Mat::identity(3));
+        assert_eq!(a.rank(), Ok(3));
+        assert_eq!(b.rank(), Ok(2));
+
+        // second row is twice the first, so the matrix is singular
+        let singular = Mat::from_array(&[[r("1"), r("2")], [r("2"), r("4")]]);
+        assert_eq!(singular.det(), Ok(r("0")));
+        assert_eq!(singular.inverse(), Ok(None));
+        assert_eq!(singular.rank(), Ok(1));
+        let swapped = Mat::from_array(&[[r("0"), r("1")], [r("1"), r("0")]]);
+        assert_eq!(swapped.det(), Ok(r("-1")));
+
+        let cols: Vec<Vec<Rational>> = b.iter_cols().map(|col| col.cloned().collect()).collect();
+        assert_eq!(
+            cols,
+            vec![
+                vec![r("1"), r("0"), r("1/2")],
+                vec![r("-1"), r("2"), r("0")]
+            ]
+        );
+        assert_eq!(a.iter_rows().count(), 3);
+
+        let view = a.view(1..3, 1..3);
+        assert_eq!((view.rows(), view.cols()), (2, 2));
+        assert_eq!(view[(1, 0)], r("1"));
+        assert_eq!(view.row(0), &[r("3"), r("1")]);
+        assert_eq!(
+            view.view(1..2, 0..2).to_mat(),
+            Mat::from_array(&[[r("1"), r("4")]])
+        );
+        assert_eq!(view.to_mat().det(), Ok(r("11")));
+
+        // Fibonacci numbers as powers of the recurrence matrix
+        let fib = Mat::from_array(&[[r("1"), r("1")], [r("1"), r("0")]]);
+        assert_eq!(fib.pow(0), Ok(Mat::identity(2)));
+        assert_eq!(fib.pow(90).unwrap()[(0, 1)], r("2880067194370816120"));
+        assert_eq!(fib.pow(100), Err(Overflow));
+    }
+
+    #[test]
+    fn test_mat_solve_augmented() {
+        let r = |s: &str| s.parse::<Rational>().unwrap();
+
+        // x + y = 3, x - y = 1
+        let mut mat = Mat::from_array(&[[r("1"), r("1"), r("3")], [r("1"), r("-1"), r("1")]]);
+        let solution = mat.solve_augmented().unwrap();
+        assert_eq!(solution.pivot_cols, vec![0, 1]);
+        assert!(solution.free_cols.is_empty());
+        assert_eq!(solution.solutions, Solutions::Unique(vec![r("2"), r("1")]));
+
+        // x + y = 1, 2x + 2y = 3
+        let mut mat = Mat::from_array(&[[r("1"), r("1"), r("1")], [r("2"), r("2"), r("3")]]);
+        let solution = mat.solve_augmented().unwrap();
+        assert_eq!(solution.pivot_cols, vec![0]);
+        assert_eq!(solution.free_cols, vec![1]);
+        assert_eq!(solution.solutions, Solutions::Inconsistent);
+
+        // the second column only becomes a pivot after the first row is eliminated from it
+        let mut mat = Mat::from_array(&[
+            [r("1"), r("1"), r("0"), r("1"), r("4")],
+            [r("1"), r("2"), r("1"), r("1"), r("6")],
+            [r("2"), r("3"), r("1"), r("2"), r("10")],
+        ]);
+        let original = mat.clone();
+        let solution = mat.solve_augmented().unwrap();
+        assert_eq!(solution.pivot_cols, vec![0, 1]);
+        assert_eq!(solution.free_cols, vec![2, 3]);
+        let Solutions::Affine {
+            particular,
+            null_space,
+        } = solution.solutions
+        else {
+            panic!("expected an affine solution space");
+        };
+        assert_eq!(particular, vec![r("2"), r("2"), r("0"), r("0")]);
+        assert_eq!(null_space.len(), 2);
+        assert_eq!(null_space[0][2], r("1"));
+        assert_eq!(null_space[1][3], r("1"));
+        let coefficients = original.view(0..3, 0..4).to_mat();
+        let rhs = original.view(0..3, 4..5).to_mat();
+        let column = |v: &[Rational]| {
+            let mut col = Mat::with_value(1, v.len(), r("0"));
+            for (i, value) in v.iter().enumerate() {
+                col[(i, 0)] = *value;
+            }
+            col
+        };
+        assert_eq!(&coefficients * &column(&particular), rhs);
+        for basis in &null_space {
+            let image = &coefficients * &column(basis);
+            assert!(image.iter_rows().flatten().all(|v| v.is_zero()));
+        }
+    }
+
+    fn rat(v: i64) -> Rational {
+        Rational::from_int(v)
+    }
+
+    /// The cheapest non-negative integer solution of `Ax = b`, given as `A` augmented with `b`,
+    /// found by trying every `x` with entries up to `limit`.
+    fn min_integer_by_brute_force(mat: &Mat<i64>, objective: &[i64], limit: i64) -> Option<i64> {
+        let (rows, vars) = (mat.rows(), mat.cols() - 1);
+        let mut best = None;
+        for candidate in 0..(limit + 1).pow(vars as u32) {
+            let x: Vec<i64> = (0..vars as u32)
+                .map(|i| candidate / (limit + 1).pow(i) % (limit + 1))
+                .collect();
+            let feasible = (0..rows).all(|row| {
+                (0..vars).map(|col| mat[(row, col)] * x[col]).sum::<i64>() == mat[(row, vars)]
+            });
+            if feasible {
+                let value = (0..vars).map(|col| objective[col] * x[col]).sum::<i64>();
+                best = Some(best.map_or(value, |best: i64| best.min(value)));
+            }
+        }
+        best
+    }
+
+    #[test]
+    fn test_minimize_integer_coins() {
+        // the cheapest way to make 7 from 2s and 3s uses three coins
+        let mat = Mat::from_array(&[[rat(2), rat(3), rat(7)]]);
+        let solution = mat.minimize_integer(&[rat(1), rat(1)]).unwrap().unwrap();
+        assert_eq!(solution.x, vec![rat(2), rat(1)]);
+        assert_eq!(solution.objective, rat(3));
+        // only even sums can be made from 2s
+        let mat = Mat::from_array(&[[rat(2), rat(4), rat(7)]]);
+        assert_eq!(mat.minimize_integer(&[rat(1), rat(1)]), Ok(None));
+    }
+
+    #[test]
+    fn test_minimize_integer_unbounded_variables() {
+        // nothing stops x - y = 1 from growing, but the cheapest solution is small
+        let mat = Mat::from_array(&[[rat(1), rat(-1), rat(1)]]);
+        let solution = mat.minimize_integer(&[rat(1), rat(1)]).unwrap().unwrap();
+        assert_eq!(solution.x, vec![rat(1), rat(0)]);
+        assert_eq!(solution.objective, rat(1));
+        // every solution costs the same
+        let solution = mat.minimize_integer(&[rat(1), rat(-1)]).unwrap().unwrap();
+        assert_eq!(solution.objective, rat(1));
+        assert_eq!(solution.x[0] - solution.x[1], rat(1));
+        // and growing makes it cheaper without limit
+        assert_eq!(
+            mat.minimize_integer(&[rat(-1), rat(0)]),
+            Err(IntegerProgramError::Unbounded)
+        );
+    }
+
+    #[test]
+    fn test_minimize_integer_fractional_coefficients() {
+        // x / 2 - y / 3 = 1/6 has x = 1, y = 1 as its cheapest solution
+        let mat = Mat::from_array(&[[
+            Rational::new(1, 2),
+            Rational::new(-1, 3),
+            Rational::new(1, 6),
+        ]]);
+        let solution = mat.minimize_integer(&[rat(1), rat(1)]).unwrap().unwrap();
+        assert_eq!(solution.x, vec![rat(1), rat(1)]);
+    }
+
+    #[test]
+    fn test_minimize_integer_matches_brute_force() {
+        use rand::Rng;
+        let mut rng = StdRng::seed_from_u64(21);
+        for _ in 0..30 {
+            // non-negative coefficients and no empty column bound every variable by the largest
+            // right-hand side, which makes the brute force exhaustive
+            let mut mat = Mat::with_value(5, 3, 0i64);
+            for col in 0..4 {
+                for row in 0..3 {
+                    mat[(row, col)] = rng.random_range(0..3);
+                }
+                if mat.col(col).all(|&v| v == 0) {
+                    mat[(rng.random_range(0..3), col)] = 1;
+                }
+            }
+            // a planted solution, which some systems lose again
+            let x: Vec<i64> = (0..4).map(|_| rng.random_range(0..4)).collect();
+            for row in 0..3 {
+                let b: i64 = (0..4).map(|col| mat[(row, col)] * x[col]).sum();
+                mat[(row, 4)] = b + rng.random_range(0..2);
+            }
+            let objective: Vec<i64> = (0..4).map(|_| rng.random_range(-2..4)).collect();
+            let limit = (0..3).map(|row| mat[(row, 4)]).max().unwrap();
+            let best = min_integer_by_brute_force(&mat, &objective, limit);
+
+            let mut rational = Mat::with_value(5, 3, rat(0));
+            for row in 0..3 {
+                for col in 0..5 {
+                    rational[(row, col)] = rat(mat[(row, col)]);
+                }
+            }
+            let objective: Vec<Rational> = objective.into_iter().map(rat).collect();
+            let solution = rational.minimize_integer(&objective).unwrap();
+            assert_eq!(
+                solution.as_ref().map(|s| s.objective),
+                best.map(rat),
+                "{}",
+                rational
+            );
+            if let Some(solution) = solution {
+                assert!(
+                    solution
+                        .x
+                        .iter()
+                        .all(|v| v.to_int().is_some_and(|v| v >= 0))
+                );
+                let value = (0..4).fold(rat(0), |acc, col| acc + objective[col] * solution.x[col]);
+                assert_eq!(value, solution.objective);
+            }
+        }
+    }
+
+    /// Certifies optimality: `x` and the duals are both feasible with equal objective values.
+    fn assert_linear_optimal(
+        mat: &Mat<Rational>,
+        objective: &[Rational],
+        solution: &LinearSolution,
+    ) {
+        let vars = objective.len();
+        assert!(solution.x.iter().all(|v| *v >= rat(0)));
+        for row in 0..mat.rows() {
+            let lhs = (0..vars).fold(rat(0), |acc, col| acc + mat[(row, col)] * solution.x[col]);
+            assert_eq!(lhs, mat[(row, vars)]);
+        }
+        for col in 0..vars {
+            let reduced = (0..mat.rows()).fold(objective[col], |acc, row| {
+                acc - solution.duals[row] * mat[(row, col)]
+            });
+            assert!(reduced >= rat(0), "dual infeasible in column {}", col);
+        }
+        let dual_objective = (0..mat.rows()).fold(rat(0), |acc, row| {
+            acc + solution.duals[row] * mat[(row, vars)]
+        });
+        let primal_objective =
+            (0..vars).fold(rat(0), |acc, col| acc + objective[col] * solution.x[col]);
+        assert_eq!(dual_objective, solution.objective);
+        assert_eq!(primal_objective, solution.objective);
+    }
+
+    /// A program with up to four rows and six variables, made feasible by a planted solution,
+    /// and positive costs so it can't be unbounded.
+    fn random_feasible_program(rng: &mut StdRng) -> (Mat<Rational>, Vec<Rational>) {
+        use rand::Rng;
+        let (rows, vars) = (rng.random_range(1..5), rng.random_range(2..7));
+        let mut mat = Mat::with_value(vars + 1, rows, rat(0));
+        let x: Vec<i64> = (0..vars).map(|_| rng.random_range(0..5)).collect();
+        for row in 0..rows {
+            let coefficients: Vec<i64> = (0..vars).map(|_| rng.random_range(-3..4)).collect();
+            for col in 0..vars {
+                mat[(row, col)] = rat(coefficients[col]);
+            }
+            mat[(row, vars)] = rat((0..vars).map(|col| coefficients[col] * x[col]).sum());
+        }
+        let objective = (0..vars).map(|_| rat(rng.random_range(1..6))).collect();
+        (mat, objective)
+    }
+
+    #[test]
+    fn test_minimize_linear_with_slack() {
+        // max x + 2y subject to x + y <= 4, x + 3y <= 6, with slack variables
+        let mat = Mat::from_array(&[
+            [rat(1), rat(1), rat(1), rat(0), rat(4)],
+            [rat(1), rat(3), rat(0), rat(1), rat(6)],
+        ]);
+        let objective = [rat(-1), rat(-2), rat(0), rat(0)];
+        let LinearOptimum::Optimal(solution) = mat.minimize_linear(&objective).unwrap() else {
+            panic!("expected an optimum");
+        };
+        assert_eq!(solution.x, vec![rat(3), rat(1), rat(0), rat(0)]);
+        assert_eq!(solution.objective, rat(-5));
+        assert_eq!(
+            solution.duals,
+            vec!["-1/2".parse().unwrap(), "-1/2".parse().unwrap()]
+        );
+        assert_linear_optimal(&mat, &objective, &solution);
+    }
+
+    #[test]
+    fn test_minimize_linear_infeasible_and_unbounded() {
+        let mat = Mat::from_array(&[[rat(1), rat(1), rat(-1)]]);
+        assert_eq!(
+            mat.minimize_linear(&[rat(1), rat(1)]),
+            Ok(LinearOptimum::Infeasible)
+        );
+        let mat = Mat::from_array(&[[rat(1), rat(-1), rat(1)]]);
+        assert_eq!(
+            mat.minimize_linear(&[rat(-1), rat(0)]),
+            Ok(LinearOptimum::Unbounded)
+        );
+    }
+
+    #[test]
+    fn test_minimize_linear_redundant_rows() {
+        // the second row is the first negated and doubled
+        let mat = Mat::from_array(&[
+            [rat(1), rat(2), rat(1), rat(5)],
+            [rat(-2), rat(-4), rat(-2), rat(-10)],
+            [rat(0), rat(1), rat(-1), rat(1)],
+        ]);
+        let objective = [rat(2), rat(1), rat(3)];
+        let LinearOptimum::Optimal(solution) = mat.minimize_linear(&objective).unwrap() else {
+            panic!("expected an optimum");
+        };
+        assert_eq!(solution.objective, rat(5));
+        assert_linear_optimal(&mat, &objective, &solution);
+    }
+
+    #[test]
+    fn test_minimize_linear_random_programs() {
+        let mut rng = StdRng::seed_from_u64(22);
+        for _ in 0..50 {
+            let (mat, objective) = random_feasible_program(&mut rng);
+            let LinearOptimum::Optimal(solution) = mat.minimize_linear(&objective).unwrap() else {
+                panic!("expected an optimum for {}", mat);
+            };
+            assert_linear_optimal(&mat, &objective, &solution);
+            // the relaxation never costs more than the integer optimum
+            if let Ok(Some(integer)) = mat.minimize_integer(&objective) {
+                assert!(solution.objective <= integer.objective);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bareiss() {
+        let a = Mat::from_array(&[[2i64, 4, 4], [-6, 6, 12], [10, -4, -16]]);
+        assert_eq!(a.bareiss_det(), Ok(-144));
+        let mut echelon = a.clone();
+        assert_eq!(echelon.bareiss(), Ok(vec![0, 1, 2]));
+        assert_eq!(echelon[(2, 2)], -144);
+        let singular = Mat::from_array(&[[1i128, 2], [2, 4]]);
+        assert_eq!(singular.bareiss_det(), Ok(0));
+    }
+
+    #[test]
+    fn test_integer_normal_forms() {
+        let a = Mat::from_array(&[[2i64, 4, 4], [-6, 6, 12], [10, -4, -16]]);
+        let hermite = a.hermite_normal_form().unwrap();
+        assert_eq!(
+            hermite.h,
+            Mat::from_array(&[[2, 4, 4], [0, 6, 0], [0, 0, 12]])
+        );
+        assert_eq!(&hermite.u * &a, hermite.h);
+        let smith = a.smith_normal_form().unwrap();
+        assert_eq!(
+            smith.s,
+            Mat::from_array(&[[2, 0, 0], [0, 6, 0], [0, 0, 12]])
+        );
+        assert_eq!(&(&smith.u * &a) * &smith.v, smith.s);
+    }
+
+    #[test]
+    fn test_integer_null_space() {
+        // x + 2y + 3z = 0 has a two dimensional lattice of solutions
+        let null_space = Mat::from_array(&[[1i64, 2, 3]])
+            .integer_null_space()
+            .unwrap();
+        assert_eq!(null_space.len(), 2);
+        for basis in &null_space {
+            assert_eq!(basis[0] + 2 * basis[1] + 3 * basis[2], 0);
+        }
+    }
+
+    #[test]
+    fn test_solve_diophantine() {
+        // 2x = 3 has a rational but no integer solution
+        assert_eq!(
+            Mat::from_array(&[[2i64, 3]]).solve_diophantine(),
+            Ok(Solutions::Inconsistent)
+        );
+        assert_eq!(
+            Mat::from_array(&[[2i64, 0, 4], [0, 3, 9]]).solve_diophantine(),
+            Ok(Solutions::Unique(vec![2, 3]))
+        );
+    }
+
+    /// A matrix of up to four rows and columns with small entries.
+    fn random_integer_mat(rng: &mut StdRng) -> Mat<i64> {
+        use rand::Rng;
+        let (rows, cols) = (rng.random_range(1..5), rng.random_range(1..5));
+        let mut mat = Mat::with_value(cols, rows, 0i64);
+        for row in 0..rows {
+            for col in 0..cols {
+                mat[(row, col)] = rng.random_range(-4..5);
+            }
+        }
+        mat
+    }
+
+    #[test]
+    fn test_integer_elimination_random() {
+        use rand::Rng;
+        let mut rng = StdRng::seed_from_u64(24);
+        for _ in 0..100 {
+            let mat = random_integer_mat(&mut rng);
+            let (rows, cols) = (mat.rows(), mat.cols());
+            if rows == cols {
+                let mut rational: Mat<Rational> =
+                    Mat::with_value(cols, rows, Rational::from_int(0));
+                for row in 0..rows {
+                    for col in 0..cols {
+                        rational[(row, col)] = Rational::from_int(mat[(row, col)]);
+                    }
+                }
+                assert_eq!(
+                    Ok(Rational::from_int(mat.bareiss_det().unwrap())),
+                    rational.det()
+                );
+            }
+
+            let HermiteForm { h, u } = mat.hermite_normal_form().unwrap();
+            assert_eq!(&u * &mat, h);
+            assert_eq!(u.bareiss_det().unwrap().abs(), 1);
+
+            let SmithForm { u, s, v } = mat.smith_normal_form().unwrap();
+            assert_eq!(&(&u * &mat) * &v, s);
+            assert_eq!(u.bareiss_det().unwrap().abs(), 1);
+            assert_eq!(v.bareiss_det().unwrap().abs(), 1);
+            for row in 0..rows {
+                for col in 0..cols {
+                    if row != col {
+                        assert_eq!(s[(row, col)], 0);
+                    }
+                }
+            }
+            for i in 1..rows.min(cols) {
+                let (prev, cur) = (s[(i - 1, i - 1)], s[(i, i)]);
+                assert!(prev >= 0 && (cur == 0 || (prev != 0 && cur % prev == 0)));
+            }
+
+            for basis in mat.integer_null_space().unwrap() {
+                assert!(mat.integer_mul_vec(&basis).unwrap().iter().all(|&v| v == 0));
+            }
+
+            // plant an integer solution and make sure a solution is found
+            let planted: Vec<i64> = (0..cols).map(|_| rng.random_range(-3..4)).collect();
+            let rhs = mat.integer_mul_vec(&planted).unwrap();
+            let mut augmented = Mat::with_value(cols + 1, rows, 0i64);
+            for row in 0..rows {
+                for col in 0..cols {
+                    augmented[(row, col)] = mat[(row, col)];
+                }
+                augmented[(row, cols)] = rhs[row];
+            }
+            match augmented.solve_diophantine().unwrap() {
+                Solutions::Inconsistent => panic!("planted solution not found"),
+                Solutions::Unique(x) => assert_eq!(x, planted),
+                Solutions::Affine {
+                    particular,
+                    null_space,
+                } => {
+                    assert_eq!(mat.integer_mul_vec(&particular).unwrap(), rhs);
+                    assert_eq!(
+                        null_space.len(),
+                        cols - s
+                            .iter_rows()
+                            .filter(|row| row.iter().any(|&v| v != 0))
+                            .count()
+                    );
+                    for basis in null_space {
+                        assert!(mat.integer_mul_vec(&basis).unwrap().iter().all(|&v| v == 0));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_num_ext_primitives_and_modint() {
+        let a = Mat::from_array(&[[2i64, 1], [1, 1]]);
+        assert_eq!(&a * &Mat::identity(2), a);
+        assert_eq!(a.pow(2), Ok(Mat::from_array(&[[5, 3], [3, 2]])));
+        assert_eq!(
+            Mat::from_array(&[[i8::MAX, 1]]).checked_add(&Mat::from_array(&[[1, 1]])),
+            Err(Overflow)
+        );
+
+        // partial pivoting avoids dividing by the tiny leading coefficient
+        let mut mat = Mat::from_array(&[[1e-8, 1.0, 1.0], [1.0, 1.0, 2.0]]);
+        assert_eq!(mat.to_rref(true), Ok(vec![0, 1]));
+        let x = 1.0 / (1.0 - 1e-8);
+        assert!((mat[(0, 2)] - x).abs() < 1e-12 && (mat[(1, 2)] - (2.0 - x)).abs() < 1e-12);
+        // the second row is a multiple of the first up to rounding
+        let mut mat = Mat::from_array(&[[0.1, 0.2, 0.3], [0.3, 0.6, 0.9]]);
+        let solution = mat.solve_augmented().unwrap();
+        assert_eq!(solution.pivot_cols, vec![0]);
+        assert!(matches!(solution.solutions, Solutions::Affine { .. }));
+        assert_eq!(
+            Mat::from_array(&[[1.0, 0.0], [0.0, 0.0]]).inverse(),
+            Ok(None)
+        );
+        assert_eq!(
+            Mat::from_array(&[[1.0, 1.0]]).checked_div_scalar(&0.0),
+            Err(Overflow)
+        );
+
+        type F7 = ModInt<7>;
+        let m = |v: i64| F7::from(v);
+        assert_eq!(m(-1), m(6));
+        assert_eq!(m(3) * m(5), m(1));
+        assert_eq!(m(1) / m(3), m(5));
+        assert_eq!(m(3).inverse(), Some(m(5)));
+        assert_eq!(m(0).inverse(), None);
+        assert_eq!(m(3).pow(6), m(1));
+        assert_eq!(m(0).checked_div(&m(0)), None);
+        assert_eq!(format!("{}", m(-2)), "5");
+
+        // the determinant is 7, so this is singular over GF(7) only
+        let singular = Mat::from_array(&[[m(1), m(2)], [m(3), m(13)]]);
+        assert_eq!(singular.rank(), Ok(1));
+        let b = Mat::from_array(&[[m(2), m(1), m(4)], [m(1), m(3), m(0)], [m(5), m(0), m(1)]]);
+        let inverse = b.inverse().unwrap().unwrap();
+        assert_eq!(&b * &inverse, Mat::identity(3));
+
+        // x + 2y = 3, 4x + 3y = 5 over GF(7)
+        let mut system = Mat::from_array(&[[m(1), m(2), m(3)], [m(4), m(3), m(5)]]);
+        let Solutions::Unique(x) = system.solve_augmented().unwrap().solutions else {
+            panic!("expected a unique solution");
+        };
+        assert_eq!(x[0] + m(2) * x[1], m(3));
+        assert_eq!(m(4) * x[0] + m(3) * x[1], m(5));
+    }
+
+    #[test]
+    fn test_integer_mat_has_no_field_ops() {
+        // an inherent const only shadows the trait's fallback where `T: FieldExt` holds, so
+        // this fails to compile if the wrong element types get `det`, `inverse`, `rank`,
+        // `to_rref` and `solve_augmented`
+        struct Probe<T>(std::marker::PhantomData<T>);
+        trait NotField {
+            const IS_FIELD: bool = false;
+        }
+        impl<T> NotField for Probe<T> {}
+        impl<T: FieldExt> Probe<T> {
+            const IS_FIELD: bool = true;
+        }
+        const {
+            assert!(!Probe::<i8>::IS_FIELD);
+            assert!(!Probe::<i64>::IS_FIELD);
+            assert!(!Probe::<i128>::IS_FIELD);
+            assert!(Probe::<f64>::IS_FIELD);
+            assert!(Probe::<Rational>::IS_FIELD);
+            assert!(Probe::<Rational<BigInt>>::IS_FIELD);
+            assert!(Probe::<ModInt<7>>::IS_FIELD);
+        }
+
+        // truncating elimination would compute 2 * (1 - 1 / 2) = 2 here
+        let a = Mat::from_array(&[[2i64, 1], [1, 1]]);
+        assert_eq!(a.bareiss_det(), Ok(1));
+        assert_eq!(
+            Mat::from_array(&[[2, 1], [1, 1]].map(|row| row.map(Rational::<i64>::from_int))).det(),
+            Ok(Rational::from_int(1))
+        );
+        let mut singular = Mat::from_array(&[[1i64, 2], [2, 4]]);
+        assert_eq!(singular.bareiss().map(|pivots| pivots.len()), Ok(1));
+        // the ring operations stay available
+        assert_eq!(&(&a * &a) - &a, Mat::from_array(&[[3, 2], [2, 1]]));
+        assert_eq!(a.transpose(), a);
+    }
+
+    /// Whether `x` solves the system whose right-hand side is the last column of `mat`.
+    fn bit_satisfies(mat: &BitMat, x: &[bool]) -> bool {
+        (0..mat.rows()).all(|row| {
+            let lhs = (0..x.len())
+                .filter(|&col| x[col] && mat.get(row, col))
+                .count()
+                % 2
+                == 1;
+            lhs == mat.get(row, x.len())
+        })
+    }
+
+    /// A matrix whose bits are each set with probability `density`.
+    fn random_bitmat(rng: &mut StdRng, cols: usize, rows: usize, density: f64) -> BitMat {
+        use rand::Rng;
+        let mut mat = BitMat::new(cols, rows);
+        for row in 0..rows {
+            for col in 0..cols {
+                mat.set(row, col, rng.random_bool(density));
+            }
+        }
+        mat
+    }
+
+    #[test]
+    fn test_bitmat_solve_augmented() {
+        use rand::Rng;
+        let mut rng = StdRng::seed_from_u64(23);
+        // 150 unknowns spread over three words per row, with a planted solution
+        let (vars, rows) = (150, 140);
+        let mut mat = random_bitmat(&mut rng, vars + 1, rows, 0.1);
+        let planted: Vec<bool> = (0..vars).map(|_| rng.random_bool(0.3)).collect();
+        for row in 0..rows {
+            let rhs = (0..vars)
+                .filter(|&col| planted[col] && mat.get(row, col))
+                .count()
+                % 2;
+            mat.set(row, vars, rhs == 1);
+        }
+        let original = mat.clone();
+        let solution = mat.clone().solve_augmented();
+        assert_eq!(solution.pivot_cols.len() + solution.free_cols.len(), vars);
+        let Solutions::Affine {
+            particular,
+            null_space,
+        } = solution.solutions
+        else {
+            panic!("expected free variables");
+        };
+        assert!(bit_satisfies(&original, &particular));
+        assert!(null_space.iter().all(|basis| {
+            let mut x = particular.clone();
+            x.iter_mut().zip(basis).for_each(|(x, b)| *x ^= b);
+            bit_satisfies(&original, &x)
+        }));
+        let pivots = mat.to_rref(true);
+        assert_eq!(pivots, solution.pivot_cols);
+        assert!((0..pivots.len()).all(|row| mat.get(row, pivots[row])));
+    }
+
+    #[test]
+    fn test_bitmat_inconsistent() {
+        // flipping the right-hand side of a row without coefficients is inconsistent
+        let mut inconsistent = BitMat::new(3, 2);
+        inconsistent.set(0, 0, true);
+        inconsistent.set(1, 2, true);
+        assert_eq!(
+            inconsistent.clone().solve_augmented().solutions,
+            Solutions::Inconsistent
+        );
+        assert_eq!(inconsistent.solutions().unwrap().count(), 0);
+        assert_eq!(inconsistent.min_weight_solution(), None);
+    }
+
+    #[test]
+    fn test_bitmat_many_free_variables() {
+        // 64 free variables are too many to enumerate, but not to search
+        let mut wide = BitMat::new(65, 1);
+        assert!(wide.solutions().is_err());
+        assert_eq!(wide.min_weight_solution(), Some(vec![false; 64]));
+        wide.set(0, 0, true);
+        assert!(wide.solutions().is_ok());
+        wide.set(0, 64, true);
+        let mut expected = vec![false; 64];
+        expected[0] = true;
+        assert_eq!(wide.min_weight_solution(), Some(expected));
+    }
+
+    #[test]
+    fn test_bitmat_min_weight_matches_brute_force() {
+        use rand::Rng;
+        let mut rng = StdRng::seed_from_u64(23);
+        for _ in 0..20 {
+            let (vars, rows) = (10, rng.random_range(2..8));
+            let mat = random_bitmat(&mut rng, vars + 1, rows, 0.4);
+            let mut all: Vec<Vec<bool>> = (0..1u32 << vars)
+                .map(|bits| (0..vars).map(|col| bits >> col & 1 == 1).collect())
+                .filter(|x: &Vec<bool>| bit_satisfies(&mat, x))
+                .collect();
+            let mut enumerated: Vec<Vec<bool>> = mat.solutions().unwrap().collect();
+            enumerated.sort();
+            all.sort();
+            assert_eq!(enumerated, all);
+            let weight = |x: &Vec<bool>| x.iter().filter(|&&b| b).count();
+            let min = mat.min_weight_solution();
+            assert_eq!(min.as_ref().map(weight), all.iter().map(weight).min());
+            assert!(min.is_none_or(|x| bit_satisfies(&mat, &x)));
+        }
+        // and against every solution with more free variables than brute force can try
+        for _ in 0..5 {
+            let mat = random_bitmat(&mut rng, 41, 24, 0.3);
+            let weight = |x: &Vec<bool>| x.iter().filter(|&&b| b).count();
+            let min = mat.min_weight_solution();
+            assert!(min.is_some(), "{}", mat);
+            assert_eq!(
+                min.as_ref().map(weight),
+                mat.solutions().unwrap().map(|x| weight(&x)).min()
+            );
+            assert!(min.is_none_or(|x| bit_satisfies(&mat, &x)));
+        }
+    }
 }
@@ -51,6 +51,7 @@ impl Timings {
             }
         }
 
+        #[allow(clippy::unnecessary_sort_by)]
         data.sort_unstable_by(|a, b| a.day.cmp(&b.day));
         Timings { data }
     }